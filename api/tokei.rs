@@ -1,10 +1,37 @@
-use std::time::Duration;
+use std::borrow::Cow;
+use std::env;
+use std::sync::LazyLock;
+use std::time::{Duration, SystemTime};
 
-use eyre::eyre;
+use http::StatusCode;
+use http::header::{CONTENT_TYPE, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED};
+use sha2::{Digest, Sha256};
+use tracing::{info, warn};
 use vercel_runtime::{Body, Error, Request, Response};
+use vercel_tokei::content_type::ContentType;
+use vercel_tokei::render;
 use vercel_tokei::util::internal_server_error;
 
 const SECONDS_IN_MINUTE: u64 = 60;
+const README: &str = include_str!("../README.md");
+// Fall back to the embedded copy quickly instead of holding up the page on a slow host
+const README_TIMEOUT: Duration = Duration::from_secs(5);
+
+static CLIENT: LazyLock<reqwest::Client> = LazyLock::new(|| {
+    reqwest::Client::builder()
+        .timeout(README_TIMEOUT)
+        .build()
+        .expect("README client should build")
+});
+
+#[derive(Clone)]
+struct Page {
+    html: String,
+    etag: String,
+    // Only known for fetched READMEs. The embedded copy relies on the ETag alone, since the time
+    // it was first rendered differs between instances.
+    last_modified: Option<SystemTime>,
+}
 
 #[cached::proc_macro::cached(
     name = "CACHE",
@@ -16,22 +43,87 @@ const SECONDS_IN_MINUTE: u64 = 60;
     convert = r#"{ url.to_string() }"#
 )]
 async fn fetch_readme(url: &str) -> Result<cached::Return<String>, Box<dyn std::error::Error>> {
-    let res = reqwest::get(url).await?;
+    let res = CLIENT.get(url).send().await?.error_for_status()?;
     let text = res.text().await?;
     Ok(cached::Return::new(text))
 }
 
-async fn handler(_req: Request) -> Result<Response<Body>, Error> {
-    let text = fetch_readme("https://raw.githubusercontent.com/aschey/vercel-tokei/main/README.md")
-        .await
-        .map_err(internal_server_error)?;
-    Response::builder()
-        .body(
-            markdown::to_html_with_options(&text, &markdown::Options::gfm())
-                .map_err(|e| eyre!("error parsing markdown: {e:?}"))?
-                .into(),
-        )
-        .map_err(|e| internal_server_error(Box::new(e)))
+#[cached::proc_macro::cached(
+    name = "PAGE_CACHE",
+    ty = "cached::SizedCache<String, Page>",
+    create = "{ cached::SizedCache::with_size(1) }",
+    convert = r#"{ readme.to_string() }"#
+)]
+fn render_page(readme: &str) -> Page {
+    let embedded = readme == README;
+    let readme = markdown::to_html_with_options(readme, &markdown::Options::gfm())
+        .inspect_err(|e| warn!("error parsing markdown: {e:?}"))
+        .ok();
+    let html = render::builder(readme.as_deref());
+    let etag = format!("\"{:x}\"", Sha256::digest(&html));
+    // HTTP dates only have second precision, so truncate here to keep comparisons consistent
+    let last_modified = (!embedded).then(|| {
+        httpdate::parse_http_date(&httpdate::fmt_http_date(SystemTime::now()))
+            .unwrap_or_else(|_| SystemTime::now())
+    });
+    Page {
+        html,
+        etag,
+        last_modified,
+    }
+}
+
+async fn load_readme() -> Cow<'static, str> {
+    // Forks can point this at their own docs, otherwise use the copy built into the binary
+    let Ok(url) = env::var("README_URL") else {
+        return Cow::Borrowed(README);
+    };
+    match fetch_readme(&url).await {
+        Ok(text) => Cow::Owned(text.value),
+        Err(e) => {
+            warn!("error fetching readme from {url}, using embedded copy: {e:?}");
+            Cow::Borrowed(README)
+        }
+    }
+}
+
+fn is_not_modified(req: &Request, page: &Page) -> bool {
+    let headers = req.headers();
+    if let Some(if_none_match) = headers.get(IF_NONE_MATCH) {
+        return if_none_match.to_str().is_ok_and(|tags| {
+            tags.split(',')
+                .map(|t| t.trim().trim_start_matches("W/"))
+                .any(|t| t == "*" || t == page.etag)
+        });
+    }
+    let Some(last_modified) = page.last_modified else {
+        return false;
+    };
+    headers
+        .get(IF_MODIFIED_SINCE)
+        .and_then(|since| since.to_str().ok())
+        .and_then(|since| httpdate::parse_http_date(since).ok())
+        .is_some_and(|since| last_modified <= since)
+}
+
+async fn handler(req: Request) -> Result<Response<Body>, Error> {
+    let readme = load_readme().await;
+    let page = render_page(&readme);
+
+    let mut builder = Response::builder().header(ETAG, &page.etag);
+    if let Some(last_modified) = page.last_modified {
+        builder = builder.header(LAST_MODIFIED, httpdate::fmt_http_date(last_modified));
+    }
+    let response = if is_not_modified(&req, &page) {
+        info!("Page not modified");
+        builder.status(StatusCode::NOT_MODIFIED).body("".into())
+    } else {
+        builder
+            .status(StatusCode::OK)
+            .header(CONTENT_TYPE, ContentType::Html.response_type())
+            .body(page.html.into())
+    };
+    response.map_err(|e| internal_server_error(Box::new(e)))
 }
 
 #[tokio::main(flavor = "current_thread")]
@@ -1,28 +1,66 @@
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::collections::{BTreeSet, HashMap};
 use std::fs::DirEntry;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{LazyLock, Mutex, MutexGuard, OnceLock};
 use std::time::{Duration, SystemTime};
-use std::{fs, io, process};
+use std::{fs, io};
 
-use cached::Cached;
 use eyre::Context;
-use git2::build::RepoBuilder;
-use git2::{Direction, FetchOptions, Remote, RemoteHead};
+use git2::build::{CheckoutBuilder, RepoBuilder};
+use git2::{Direction, FetchOptions, RemoteCallbacks, RemoteHead, RemoteRedirect, Repository};
+use http::header::{ACCEPT, CONTENT_TYPE, RETRY_AFTER, VARY};
 use http::{Method, StatusCode};
-use rsbadges::Badge;
 use tempfile::TempDir;
 use tokei::{Config, Language, LanguageType, Languages};
 use tracing::{error, info, warn};
 use url::Url;
 use vercel_runtime::{Body, Error, Request, Response};
+use vercel_tokei::cache::{self, StatsCache};
+use vercel_tokei::category::Category;
 use vercel_tokei::content_type::ContentType;
+use vercel_tokei::credentials::{Credential, Credentials};
+use vercel_tokei::error::ApiError;
+use vercel_tokei::fetch::{self, FetchStrategy};
+use vercel_tokei::forge::{self, Forge, ForgeRegistry, Transport};
+use vercel_tokei::forge_api::{self, ForgeApi, Source};
+use vercel_tokei::host_policy::HostPolicy;
+use vercel_tokei::known_hosts::KnownHosts;
+use vercel_tokei::limits::CloneLimits;
 use vercel_tokei::settings::Settings;
+use vercel_tokei::signing::Signer;
+use vercel_tokei::theme::Theme;
 use vercel_tokei::util::internal_server_error;
+use vercel_tokei::{params, render};
 
-const BILLION: usize = 1_000_000_000;
-const MILLION: usize = 1_000_000;
-const THOUSAND: usize = 1_000;
 const DAY_IN_SECONDS: u64 = 24 * 60 * 60;
+const CACHE_TTL: Duration = Duration::from_secs(DAY_IN_SECONDS);
+const STALE_CACHE_TTL: Duration = Duration::from_secs(7 * DAY_IN_SECONDS);
 const REVALIDATE_FACTOR: u32 = 5;
+// Keep errors from sticking in the edge cache after the underlying issue is resolved
+const ERROR_CACHE_SECONDS: u32 = 10;
+const TEMP_PREFIX: &str = "tokei-cache";
+// Kept apart from the clones so clearing them never removes it
+const NO_REDIRECT_PREFIX: &str = "tokei-remote";
+const TEMP_DIR_MIN_AGE: Duration = Duration::from_secs(60);
+const UNAVAILABLE_RETRY_AFTER: Duration = Duration::from_secs(60);
+
+static CACHE: LazyLock<Box<dyn StatsCache>> = LazyLock::new(cache::from_env);
+static CREDENTIALS: LazyLock<Credentials> = LazyLock::new(Credentials::from_env);
+static SIGNER: LazyLock<Option<Signer>> = LazyLock::new(Signer::from_env);
+static HOST_POLICY: LazyLock<HostPolicy> = LazyLock::new(HostPolicy::from_env);
+static KNOWN_HOSTS: LazyLock<KnownHosts> = LazyLock::new(KnownHosts::from_env);
+static FORGES: LazyLock<ForgeRegistry> = LazyLock::new(ForgeRegistry::from_env);
+static CLONE_LIMITS: LazyLock<CloneLimits> = LazyLock::new(CloneLimits::from_env);
+static FETCH_STRATEGY: LazyLock<FetchStrategy> = LazyLock::new(FetchStrategy::from_env);
+// Repository that remotes are created from, set up once so connecting doesn't need disk space
+static NO_REDIRECT_DIR: OnceLock<TempDir> = OnceLock::new();
+// Clones in progress, which must survive cleanup no matter how long they take
+static LIVE_CLONE_DIRS: Mutex<BTreeSet<PathBuf>> = Mutex::new(BTreeSet::new());
+// Cleared when temporary storage can't be recovered so the health check can ask the platform to
+// replace the container, without dropping requests that are still in flight
+static HEALTHY: AtomicBool = AtomicBool::new(true);
 
 async fn handler(req: Request) -> Result<Response<Body>, Error> {
     tokio::task::spawn_blocking(|| handle_request(req))
@@ -33,12 +71,22 @@ async fn handler(req: Request) -> Result<Response<Body>, Error> {
 fn handle_request(req: Request) -> Result<Response<Body>, Error> {
     // For health checks
     if req.method() == Method::HEAD {
-        return Ok(Response::new("".into()));
+        let status = if HEALTHY.load(Ordering::Relaxed) {
+            StatusCode::OK
+        } else {
+            StatusCode::SERVICE_UNAVAILABLE
+        };
+        return Response::builder()
+            .status(status)
+            .body("".into())
+            .map_err(|e| internal_server_error(Box::new(e)));
     }
 
     let parsed_url =
         Url::parse(&req.uri().to_string()).map_err(|e| internal_server_error(Box::new(e)))?;
 
+    // Parameter names are case-insensitive, so everything that reads the query looks keys up in
+    // lowercase
     let hash_query: HashMap<_, _> = parsed_url
         .query_pairs()
         .map(|p| (p.0.to_ascii_lowercase(), p.1))
@@ -46,110 +94,227 @@ fn handle_request(req: Request) -> Result<Response<Body>, Error> {
 
     info!("Query pairs: {hash_query:?}");
 
-    let settings = match Settings::from_query(&hash_query) {
-        Ok(settings) => settings,
-        Err(e) => return bad_request(e.to_string()),
-    };
-    let language_filter: Option<Vec<LanguageType>> = if let Some(languages) = &settings.languages {
-        let languages: Result<Vec<LanguageType>, Result<Response<Body>, Error>> = languages
-            .iter()
-            .map(|l| {
-                LanguageType::from_name(l).ok_or_else(|| {
-                    bad_request(format!(
-                        "Unknown language: {l}. Please note that languages are case sensitive and \
-                         should be capitalized."
-                    ))
-                })
-            })
-            .collect();
-        match languages {
-            Ok(l) => Some(l),
-            Err(e) => return e,
+    match get_response(&req, &parsed_url, &hash_query) {
+        Ok(response) => Ok(response),
+        Err(e) => {
+            info!("Request failed: {e}");
+            // Settings may have failed to parse, so figure out the requested format on its own
+            let content_type = requested_content_type(&req, &hash_query).unwrap_or_default();
+            let theme = Theme::from_query(&hash_query).unwrap_or_default();
+            error_response(&e, content_type, &theme)
         }
-    } else {
-        None
-    };
+    }
+}
+
+fn get_response(
+    req: &Request,
+    parsed_url: &Url,
+    hash_query: &HashMap<String, Cow<str>>,
+) -> Result<Response<Body>, ApiError> {
+    let mut settings = Settings::from_query(hash_query)?;
+    settings.content_type = requested_content_type(req, hash_query)?;
+    let language_filter = settings.language_filter()?;
 
     let (domain, user, repo) = (
         hash_query
             .get("domain")
-            .ok_or_else(|| internal_server_error("domain missing".into()))?,
+            .ok_or_else(|| ApiError::internal("domain missing"))?,
         hash_query
             .get("user")
-            .ok_or_else(|| internal_server_error("user missing".into()))?,
+            .ok_or_else(|| ApiError::internal("user missing"))?,
         hash_query
             .get("repo")
-            .ok_or_else(|| internal_server_error("repo missing".into()))?,
+            .ok_or_else(|| ApiError::internal("repo missing"))?,
     );
-    let mut domain = percent_encoding::percent_decode_str(domain)
+    let domain = percent_encoding::percent_decode_str(domain)
         .decode_utf8()
-        .wrap_err_with(|| "Error decoding domain")?;
-
-    if !domain.contains('.') {
-        domain += ".com";
-    }
-
-    let url = format!("https://{domain}/{user}/{repo}");
+        .map_err(|e| ApiError::InvalidRepo(format!("Error decoding domain: {e}")))?;
+
+    // Nested namespaces (ex: GitLab subgroups) don't fit in the route, so they're passed
+    // separately and replace the user segment
+    let namespace = params::NAMESPACE
+        .get(hash_query)
+        .map(|n| n.trim_matches('/'))
+        .unwrap_or(user.as_ref());
+    forge::validate_path(namespace)?;
+    forge::validate_path(repo)?;
+    // Credentials can be scoped to the top-level group, which also covers its subgroups
+    let owner = namespace.split('/').next().unwrap_or_default();
+
+    let forge = FORGES.resolve(&domain);
+    HOST_POLICY.check(&forge.host)?;
+
+    let url = forge.clone_url(namespace, repo);
     info!("Getting info for {url}");
-
-    let mut repo = match Remote::create_detached(&url[..]) {
-        Ok(repo) => repo,
-        Err(e) => return bad_request(e.to_string()),
+    let badge_url = badge_url(parsed_url, &format!("/tokei/{domain}/{namespace}/{repo}"));
+
+    // Detached remotes always follow the first redirect, which would let a public host send us to
+    // a private address, so connect through a repository that's configured not to
+    let remote_repo = no_redirect_repo()?;
+    let mut remote = remote_repo.remote_anonymous(&url).map_err(|e| {
+        ApiError::from_git("Invalid repository URL", &e)
+            .unwrap_or_else(|| ApiError::InvalidRepo(e.to_string()))
+    })?;
+
+    let find_credential = || match forge.transport {
+        Transport::Https => CREDENTIALS.find(&forge.host, owner),
+        Transport::Ssh => CREDENTIALS.ssh_key(),
+        Transport::Git => None,
     };
-
-    if let Err(e) = repo.connect(Direction::Fetch) {
-        return bad_request(format!("Error connecting to repository: {e}"));
+    let credential = match &*SIGNER {
+        // Once signing is enabled, credentials are only used for signed requests
+        Some(signer) if params::SIGNATURE.get(hash_query).is_some() => {
+            signer.verify(hash_query)?;
+            find_credential()
+        }
+        Some(_) => None,
+        None if params::SIGNATURE.get(hash_query).is_some() => {
+            return Err(ApiError::Forbidden(
+                "Signed URLs are not enabled on this server".to_owned(),
+            ));
+        }
+        None => find_credential(),
+    };
+    if let Some(credential) = credential {
+        info!("Using credentials for {}", credential.scope());
     }
-
-    let repo_list = match repo.list() {
-        Ok(list) => list,
-        Err(e) => return bad_request(format!("Error listing repo contents: {e}")),
+    // Links to the badge change the query, so a signed request gets links signed the same way
+    let signer = SIGNER
+        .as_ref()
+        .filter(|_| params::SIGNATURE.get(hash_query).is_some());
+    let sign = |link: Url| match signer {
+        Some(signer) => sign_link(signer, (&domain, namespace, repo), link),
+        None => link,
     };
+    let mut callbacks = credential
+        .map(Credential::remote_callbacks)
+        .unwrap_or_default();
+    KNOWN_HOSTS.verify(&forge.host, &mut callbacks);
+    remote
+        .connect_auth(Direction::Fetch, Some(callbacks), None)
+        .map_err(|e| {
+            CLONE_LIMITS
+                .timeout_error(&e)
+                .or_else(|| ApiError::from_git("Error connecting to repository", &e))
+                .unwrap_or_else(|| {
+                    ApiError::InvalidRepo(format!("Error connecting to repository: {e}"))
+                })
+        })?;
 
-    let sha = match find_sha(settings.branch.as_deref(), repo_list) {
-        Ok(sha) => sha,
-        Err(e) => return bad_request(e.to_string()),
-    };
+    let repo_list = remote.list().map_err(|e| {
+        CLONE_LIMITS
+            .timeout_error(&e)
+            .or_else(|| ApiError::from_git("Error listing repo contents", &e))
+            .unwrap_or_else(|| ApiError::InvalidRepo(format!("Error listing repo contents: {e}")))
+    })?;
+
+    let sha = find_sha(settings.branch.as_deref(), repo_list)?;
     info!("Repo sha: {sha:?} for branch {:?}", settings.branch);
 
-    if let Some(badge) = CACHE
-        .lock()
-        .expect("Cache mutex poisoned")
-        .cache_get(&cache_key(&url, &sha, &settings))
-    {
+    let key = cache_key(&url, &sha, &settings, credential);
+    if let Some(stats) = cache_get(&key) {
         info!("Serving from cache");
-        return match make_badge(&settings, badge) {
-            Ok(badge) => build_response(badge, &settings),
-            Err(e) => bad_request(e.to_string()),
+        let body =
+            render_body(&url, &badge_url, &sign, &settings, &stats).map_err(ApiError::internal)?;
+        return build_response(body, &settings);
+    }
+
+    if use_forge_api(&forge, &settings, credential)
+        && let Some(api) = &forge.api
+    {
+        let api_key = format!("{key}#api");
+        let stats = match cache_get(&api_key) {
+            Some(stats) => Ok(stats),
+            None => get_api_statistics(api, &url, namespace, repo, &sha, language_filter.clone())
+                .inspect(|stats| cache_set(&api_key, stats, CACHE_TTL)),
         };
+        match stats {
+            Ok(stats) => {
+                let body = render_body(&url, &badge_url, &sign, &settings, &stats)
+                    .map_err(ApiError::internal)?;
+                return build_response(body, &settings);
+            }
+            Err(e) => warn!("Falling back to cloning {url}: {e}"),
+        }
     }
 
-    let stats = get_statistics(&url, &sha, &settings, language_filter)
-        .wrap_err_with(|| "Error getting statistics")?
-        .value;
+    let stale_key = stale_cache_key(&url, &settings, credential);
+    let stats = match get_statistics(
+        &url,
+        &forge.host,
+        settings.branch.as_deref(),
+        credential,
+        language_filter,
+    ) {
+        Ok(stats) => {
+            cache_set(&key, &stats, CACHE_TTL);
+            cache_set(&stale_key, &stats, STALE_CACHE_TTL);
+            stats
+        }
+        Err(e @ ApiError::Unavailable { .. }) => {
+            let Some(stats) = cache_get(&stale_key) else {
+                return Err(e);
+            };
+            warn!("Serving stale statistics for {url}: {e}");
+            stats
+        }
+        Err(e) => return Err(e),
+    };
+
+    let body =
+        render_body(&url, &badge_url, &sign, &settings, &stats).map_err(ApiError::internal)?;
+    build_response(body, &settings)
+}
+
+/// The forge API only lists files, so it can only be used when the response shows nothing but the
+/// file count. Private repositories always go through git.
+fn use_forge_api(forge: &Forge, settings: &Settings, credential: Option<&Credential>) -> bool {
+    let eligible = settings.category == Category::Files
+        && matches!(
+            settings.content_type,
+            ContentType::Svg | ContentType::Png | ContentType::Text
+        )
+        && credential.is_none()
+        && forge.transport == Transport::Https;
+    eligible
+        && match settings.source {
+            Source::Auto => forge.prefer_api,
+            Source::Api => true,
+            Source::Git => false,
+        }
+}
 
-    match make_badge(&settings, &stats).map_err(internal_server_error) {
-        Ok(badge) => build_response(badge, &settings),
-        Err(e) => bad_request(e.to_string()),
+/// The format from the `format` parameter, falling back to the `Accept` header.
+fn requested_content_type(
+    req: &Request,
+    hash_query: &HashMap<String, Cow<str>>,
+) -> Result<ContentType, ApiError> {
+    if params::FORMAT.get(hash_query).is_some() {
+        return Ok(ContentType::from_query(hash_query)?);
+    }
+    match req.headers().get(ACCEPT) {
+        Some(accept) => accept
+            .to_str()
+            .ok()
+            .and_then(ContentType::from_accept)
+            .ok_or(ApiError::NotAcceptable),
+        None => Ok(ContentType::default()),
     }
 }
 
-fn find_sha(
-    branch: Option<&str>,
-    repo_list: &[RemoteHead],
-) -> Result<String, Box<dyn std::error::Error>> {
+fn find_sha(branch: Option<&str>, repo_list: &[RemoteHead]) -> Result<String, ApiError> {
     let head = match branch {
         Some(ref branch) => {
             let search = format!("refs/heads/{branch}");
             let found_branch = repo_list.iter().find(|r| r.name() == search.as_str());
             let Some(found_branch) = found_branch else {
-                return Err(format!("Requested branch {branch:?} not found").into());
+                return Err(ApiError::BranchNotFound(branch.to_string()));
             };
             found_branch
         }
         None => {
             let Some(first) = repo_list.first() else {
-                return Err("Repo contains no refs".into());
+                return Err(ApiError::RepoNotFound("Repo contains no refs".to_owned()));
             };
             first
         }
@@ -157,10 +322,11 @@ fn find_sha(
     Ok(head.oid().to_string())
 }
 
-fn build_response(badge: String, settings: &Settings) -> Result<Response<Body>, Error> {
+fn build_response(body: Body, settings: &Settings) -> Result<Response<Body>, ApiError> {
     Response::builder()
         .status(StatusCode::OK)
-        .header("Content-Type", settings.content_type.response_type())
+        .header(CONTENT_TYPE, settings.content_type.response_type())
+        .header(VARY, "Accept")
         .header(
             "Cache-Control",
             &format!(
@@ -169,140 +335,358 @@ fn build_response(badge: String, settings: &Settings) -> Result<Response<Body>,
                 settings.cache_seconds * REVALIDATE_FACTOR
             ),
         )
-        .body(badge.into())
-        .map_err(|e| internal_server_error(Box::new(e)))
+        .body(body)
+        .map_err(ApiError::internal)
 }
 
-fn bad_request(body: String) -> Result<Response<Body>, Error> {
-    Response::builder()
-        .status(StatusCode::BAD_REQUEST)
-        .body(body.into())
+fn error_response(
+    error: &ApiError,
+    content_type: ContentType,
+    theme: &Theme,
+) -> Result<Response<Body>, Error> {
+    let (body, response_type): (Body, &str) = match content_type {
+        ContentType::Svg | ContentType::Png => {
+            let badge = render::error_badge(error, theme).map_err(internal_server_error)?;
+            if content_type == ContentType::Png {
+                let png = render::png(&badge).map_err(internal_server_error)?;
+                (png.into(), content_type.response_type())
+            } else {
+                (badge.into(), content_type.response_type())
+            }
+        }
+        ContentType::Json | ContentType::Files => (
+            error.to_json().to_string().into(),
+            ContentType::Json.response_type(),
+        ),
+        _ => (error.to_string().into(), ContentType::Text.response_type()),
+    };
+
+    let mut builder = Response::builder()
+        .status(error.status())
+        .header(CONTENT_TYPE, response_type);
+    if let Some(retry_after) = error.retry_after() {
+        builder = builder.header(RETRY_AFTER, retry_after.as_secs());
+    }
+    builder
+        .header(VARY, "Accept")
+        .header(
+            "Cache-Control",
+            &format!("max-age=0, s-maxage={ERROR_CACHE_SECONDS}"),
+        )
+        .body(body)
         .map_err(|e| internal_server_error(Box::new(e)))
 }
 
-fn cache_key(url: &str, sha: &str, settings: &Settings) -> String {
-    format!("{}#{}#{}", url, sha, settings.loc_cache_key())
+fn cache_key(url: &str, sha: &str, settings: &Settings, credential: Option<&Credential>) -> String {
+    format!(
+        "{}#{}#{}#{}",
+        url,
+        sha,
+        credential_scope(credential),
+        settings.counting_fingerprint()
+    )
+}
+
+fn credential_scope(credential: Option<&Credential>) -> String {
+    // Results fetched with credentials are keyed by the credential's scope so they can't be served
+    // for requests that resolve to a different scope (or to no credentials at all).
+    credential
+        .map(Credential::scope)
+        .unwrap_or_else(|| "public".to_owned())
+}
+
+/// Key for the most recent statistics of any commit on a branch, which are kept around longer so
+/// they can be served when new statistics can't be computed.
+fn stale_cache_key(url: &str, settings: &Settings, credential: Option<&Credential>) -> String {
+    format!(
+        "{}#latest#{}#{}#{}",
+        url,
+        settings.branch.as_deref().unwrap_or("HEAD"),
+        credential_scope(credential),
+        settings.counting_fingerprint()
+    )
+}
+
+// Cache failures shouldn't fail the request, the statistics can always be recomputed
+fn cache_get(key: &str) -> Option<Language> {
+    CACHE
+        .get(key)
+        .inspect_err(|e| warn!("error reading from cache: {e:?}"))
+        .ok()
+        .flatten()
 }
 
-fn trim_and_float(num: usize, trim: usize) -> f64 {
-    (num as f64) / (trim as f64)
+fn cache_set(key: &str, stats: &Language, ttl: Duration) {
+    let _ = CACHE
+        .set(key, stats, ttl)
+        .inspect_err(|e| warn!("error writing to cache: {e:?}"));
 }
 
-fn make_badge(settings: &Settings, stats: &Language) -> Result<String, Box<dyn std::error::Error>> {
-    if settings.content_type == ContentType::Json {
-        return Ok(serde_json::to_string(&stats)?);
+fn badge_url(request_url: &Url, path: &str) -> Url {
+    let mut badge_url = request_url.clone();
+    badge_url.set_path(path);
+    badge_url.set_query(None);
+    let pairs: Vec<_> = request_url
+        .query_pairs()
+        .filter(|(k, _)| {
+            !["domain", "user", "repo", "namespace", "format", "sig"]
+                .iter()
+                .any(|key| k.eq_ignore_ascii_case(key))
+        })
+        .collect();
+    if !pairs.is_empty() {
+        badge_url.query_pairs_mut().extend_pairs(pairs);
     }
+    badge_url
+}
 
-    let amount = settings.category.stats(stats);
-    let label = match &settings.label {
-        Some(label) => label.as_str(),
-        None => settings.category.description(),
+/// Signs a link to the badge the way it will be verified, once the route rewrite has moved a nested
+/// namespace back into the query.
+fn sign_link(signer: &Signer, (domain, namespace, repo): (&str, &str, &str), mut link: Url) -> Url {
+    let mut signed_params: Vec<(String, String)> = link
+        .query_pairs()
+        .map(|(k, v)| (k.into_owned(), v.into_owned()))
+        .collect();
+    let user = match namespace.split_once('/') {
+        Some((group, _)) => {
+            signed_params.push((params::NAMESPACE.name.to_owned(), namespace.to_owned()));
+            group
+        }
+        None => namespace,
     };
+    let signature = signer.sign(domain, user, repo, &signed_params);
+    link.query_pairs_mut()
+        .append_pair(params::SIGNATURE.name, &signature);
+    link
+}
 
-    let amount = if amount >= BILLION {
-        format!("{:.1}B", trim_and_float(amount, BILLION))
-    } else if amount >= MILLION {
-        format!("{:.1}M", trim_and_float(amount, MILLION))
-    } else if amount >= THOUSAND {
-        format!("{:.1}K", trim_and_float(amount, THOUSAND))
-    } else {
-        amount.to_string()
-    };
+fn render_body(
+    url: &str,
+    badge_url: &Url,
+    sign: &dyn Fn(Url) -> Url,
+    settings: &Settings,
+    stats: &Language,
+) -> Result<Body, Box<dyn std::error::Error>> {
+    Ok(match settings.content_type {
+        ContentType::Svg => render::badge(settings, stats)?.into(),
+        ContentType::Json => serde_json::to_string(&stats)?.into(),
+        ContentType::Text => render::text(settings, stats).into(),
+        ContentType::Prometheus => render::prometheus(url, stats).into(),
+        ContentType::Csv => render::csv(settings, stats).into(),
+        ContentType::Markdown => render::markdown(settings, stats).into(),
+        ContentType::Files => render::files(settings, stats).into(),
+        ContentType::Png => render::png(&render::badge(settings, stats)?)?.into(),
+        ContentType::Html => render::html(url, badge_url, sign, settings, stats).into(),
+    })
+}
 
-    let badge = Badge {
-        label_text: label.to_owned(),
-        label_color: settings.theme.label_color.to_string(),
-        msg_text: amount.clone(),
-        msg_color: settings.theme.color.to_string(),
-        logo: settings.logo.clone().unwrap_or_default(),
-        // data urls can't be embedded
-        embed_logo: settings
-            .logo
-            .as_ref()
-            .map(|l| !l.starts_with("data:"))
-            .unwrap_or(false),
-        msg_title: amount,
-        label_title: label.to_owned(),
-        use_logo_as_label: settings.logo_as_label,
-        ..Badge::default()
+fn get_api_statistics(
+    api: &ForgeApi,
+    url: &str,
+    namespace: &str,
+    repo: &str,
+    sha: &str,
+    language_filter: Option<Vec<LanguageType>>,
+) -> Result<Language, ApiError> {
+    let paths = api.file_paths(namespace, repo, sha)?;
+    info!("Listed {} files for {url} from the forge API", paths.len());
+    let config = Config {
+        types: language_filter,
+        ..Default::default()
     };
-    let badge_style = settings.theme.style.to_badge_style(badge);
-    Ok(badge_style.generate_svg()?)
+    Ok(forge_api::file_statistics(paths, &config))
 }
 
-#[cached::proc_macro::cached(
-    name = "CACHE",
-    result = true,
-    with_cached_flag = true,
-    ty = "cached::TimedSizedCache<String, cached::Return<Language>>",
-    create = "{ cached::TimedSizedCache::with_size_and_lifespan(1000, \
-              Duration::from_secs(DAY_IN_SECONDS)) }",
-    convert = r#"{ cache_key(url, _sha, settings) }"#
-)]
+/// Counts every language in the repository. The result doesn't depend on how it's rendered, so it
+/// can be cached once and shared by every category and format.
 fn get_statistics(
     url: &str,
-    _sha: &str,
-    settings: &Settings,
+    host: &str,
+    branch: Option<&str>,
+    credential: Option<&Credential>,
     language_filter: Option<Vec<LanguageType>>,
-) -> eyre::Result<cached::Return<Language>> {
-    let temp_prefix = "tokei-cache";
-    let _ = clear_previous_files(temp_prefix).inspect_err(|e| warn!("error cleaning files: {e:?}"));
-
-    let temp_dir = match TempDir::with_prefix(temp_prefix) {
-        Ok(temp_dir) => temp_dir,
-        Err(e) => {
-            error!("Failed to create temp dir: {e:?}. Force exiting process");
-            // If we failed to create the temp dir, the disk is likely full.
-            // Force exit the process to force a new container to be created.
-            process::exit(1);
-        }
-    };
+) -> Result<Language, ApiError> {
+    let _ = clear_previous_files(TEMP_PREFIX, TEMP_DIR_MIN_AGE)
+        .inspect_err(|e| warn!("error cleaning files: {e:?}"));
+    let temp_dir = create_temp_dir()?;
 
     let temp_path = temp_dir.path();
+    let clone_guard = CLONE_LIMITS.start();
+    let mut callbacks = match credential {
+        Some(credential) => credential.remote_callbacks(),
+        None => RemoteCallbacks::new(),
+    };
+    KNOWN_HOSTS.verify(host, &mut callbacks);
+    clone_guard.watch(&mut callbacks);
     let mut fetch_opts = FetchOptions::new();
     fetch_opts.depth(1);
+    // The host policy only checked the original host
+    fetch_opts.follow_redirects(RemoteRedirect::None);
+    fetch_opts.remote_callbacks(callbacks);
     let mut repo_builder = RepoBuilder::new();
     repo_builder.fetch_options(fetch_opts);
-    if let Some(branch) = &settings.branch {
+    if let Some(branch) = branch {
         repo_builder.branch(branch);
     }
-    repo_builder
-        .clone(url, temp_path)
-        .wrap_err_with(|| "Error discovering repo")?;
-    let mut languages = Languages::new();
+    if FETCH_STRATEGY.skip_checkout() {
+        let mut checkout = CheckoutBuilder::new();
+        checkout.dry_run();
+        repo_builder.with_checkout(checkout);
+    }
+    let git_repo = repo_builder.clone(url, temp_path).map_err(|e| {
+        // Aborting the transfer surfaces as a generic callback error, so check the limits first
+        clone_guard
+            .take_error()
+            .or_else(|| CLONE_LIMITS.timeout_error(&e))
+            .or_else(|| ApiError::from_git("Error cloning repository", &e))
+            .unwrap_or_else(|| ApiError::CloneFailed(e.to_string()))
+    })?;
     let config = Config {
         types: language_filter,
         ..Default::default()
     };
+    let read_error = |e: git2::Error| {
+        ApiError::from_git("Error reading repository files", &e)
+            .unwrap_or_else(|| ApiError::CloneFailed(e.to_string()))
+    };
+
+    let stats = match *FETCH_STRATEGY {
+        FetchStrategy::Checkout => count_files(temp_path, &config)?,
+        FetchStrategy::Sparse => {
+            fetch::sparse_checkout(&git_repo, &config).map_err(read_error)?;
+            count_files(temp_path, &config)?
+        }
+        FetchStrategy::Memory => fetch::count_in_memory(&git_repo, &config).map_err(read_error)?,
+    };
 
-    languages.get_statistics(&[temp_path], &[], &config);
+    Ok(stats)
+}
+
+/// Counts the files checked out to `path`, with report names relative to it.
+fn count_files(path: &Path, config: &Config) -> Result<Language, ApiError> {
+    let mut languages = Languages::new();
+    languages.get_statistics(&[path], &[], config);
     let mut stats = languages.total();
 
-    for stat in &mut stats.reports {
-        stat.name = stat.name.strip_prefix(temp_path)?.to_owned();
+    for stat in stats
+        .reports
+        .iter_mut()
+        .chain(stats.children.values_mut().flatten())
+    {
+        stat.name = stat
+            .name
+            .strip_prefix(path)
+            .map_err(ApiError::internal)?
+            .to_owned();
+    }
+    Ok(stats)
+}
+
+/// Empty repository used to list a remote's refs before deciding whether to clone it. Connections
+/// through it won't follow redirects.
+fn no_redirect_repo() -> Result<Repository, ApiError> {
+    let path = match NO_REDIRECT_DIR.get() {
+        Some(dir) => dir.path(),
+        None => {
+            let dir = TempDir::with_prefix(NO_REDIRECT_PREFIX).map_err(|e| {
+                warn!("Failed to create temp dir: {e:?}");
+                ApiError::Unavailable {
+                    retry_after: UNAVAILABLE_RETRY_AFTER,
+                }
+            })?;
+            let repo = Repository::init_bare(dir.path()).map_err(ApiError::internal)?;
+            repo.config()
+                .and_then(|mut config| config.set_bool("http.followRedirects", false))
+                .map_err(ApiError::internal)?;
+            NO_REDIRECT_DIR.get_or_init(|| dir).path()
+        }
+    };
+    Repository::open_bare(path).map_err(ApiError::internal)
+}
+
+/// A clone's temporary directory, which the cleanup in `clear_previous_files` skips until it's
+/// dropped.
+struct CloneDir(Option<TempDir>);
+
+impl CloneDir {
+    fn new(temp_dir: TempDir) -> Self {
+        live_clone_dirs().insert(temp_dir.path().to_owned());
+        Self(Some(temp_dir))
+    }
+
+    fn path(&self) -> &Path {
+        self.0
+            .as_ref()
+            .expect("directory should exist until dropped")
+            .path()
+    }
+}
+
+impl Drop for CloneDir {
+    fn drop(&mut self) {
+        let Some(temp_dir) = self.0.take() else {
+            return;
+        };
+        live_clone_dirs().remove(temp_dir.path());
+        let _ = temp_dir
+            .close()
+            .map_err(|e| warn!("error removing temporary directory: {e:?}"));
     }
-    let _ = temp_dir
-        .close()
-        .map_err(|e| warn!("error removing temporary directory: {e:?}"));
+}
+
+fn live_clone_dirs() -> MutexGuard<'static, BTreeSet<PathBuf>> {
+    LIVE_CLONE_DIRS
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
 
-    Ok(cached::Return::new(stats))
+fn create_temp_dir() -> Result<CloneDir, ApiError> {
+    let error = match TempDir::with_prefix(TEMP_PREFIX) {
+        Ok(temp_dir) => {
+            HEALTHY.store(true, Ordering::Relaxed);
+            return Ok(CloneDir::new(temp_dir));
+        }
+        Err(e) => e,
+    };
+    // The disk is likely full. Clones that are still running are skipped, so remove everything
+    // else and try again before giving up.
+    warn!("Failed to create temp dir: {error:?}. Retrying after clearing previous files");
+    let _ = clear_previous_files(TEMP_PREFIX, Duration::ZERO)
+        .inspect_err(|e| warn!("error cleaning files: {e:?}"));
+    match TempDir::with_prefix(TEMP_PREFIX) {
+        Ok(temp_dir) => {
+            HEALTHY.store(true, Ordering::Relaxed);
+            Ok(CloneDir::new(temp_dir))
+        }
+        Err(e) => {
+            error!("Failed to create temp dir after clearing previous files: {e:?}");
+            HEALTHY.store(false, Ordering::Relaxed);
+            Err(ApiError::Unavailable {
+                retry_after: UNAVAILABLE_RETRY_AFTER,
+            })
+        }
+    }
 }
 
-fn clear_previous_files(prefix: &str) -> io::Result<()> {
+fn clear_previous_files(prefix: &str, min_age: Duration) -> io::Result<()> {
     // Previous temp files should get removed automatically, but we'll force remove any old files
     // here just in case.
     let env_temp = std::env::temp_dir();
     let cache_prefix = env_temp.join(prefix);
     let cache_prefix = cache_prefix.as_os_str().as_encoded_bytes();
     for entry in fs::read_dir(env_temp)? {
-        let _ =
-            clear_entry(entry, cache_prefix).inspect_err(|e| warn!("failed to clean entry: {e:?}"));
+        let _ = clear_entry(entry, cache_prefix, min_age)
+            .inspect_err(|e| warn!("failed to clean entry: {e:?}"));
     }
 
     Ok(())
 }
 
-fn clear_entry(entry: io::Result<DirEntry>, cache_prefix: &[u8]) -> io::Result<()> {
+fn clear_entry(
+    entry: io::Result<DirEntry>,
+    cache_prefix: &[u8],
+    min_age: Duration,
+) -> io::Result<()> {
     let entry = entry?;
     let path = entry.path();
 
@@ -311,11 +695,12 @@ fn clear_entry(entry: io::Result<DirEntry>, cache_prefix: &[u8]) -> io::Result<(
             .as_os_str()
             .as_encoded_bytes()
             .starts_with(cache_prefix)
+        && !live_clone_dirs().contains(&path)
     {
         let created = path.metadata()?.created()?;
-        if created < SystemTime::now() - Duration::from_secs(60) {
+        if created <= SystemTime::now() - min_age {
             info!("removing old path: {path:?}");
-            fs::remove_dir_all(path).unwrap();
+            fs::remove_dir_all(path)?;
         }
     }
 
@@ -325,5 +710,6 @@ fn clear_entry(entry: io::Result<DirEntry>, cache_prefix: &[u8]) -> io::Result<(
 #[tokio::main(flavor = "current_thread")]
 async fn main() -> Result<(), Error> {
     tracing_subscriber::fmt().with_ansi(false).init();
+    CLONE_LIMITS.apply_server_timeouts();
     vercel_runtime::run(handler).await
 }
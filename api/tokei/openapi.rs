@@ -0,0 +1,24 @@
+use std::sync::LazyLock;
+
+use http::StatusCode;
+use http::header::CONTENT_TYPE;
+use vercel_runtime::{Body, Error, Request, Response};
+use vercel_tokei::content_type::ContentType;
+use vercel_tokei::openapi;
+use vercel_tokei::util::internal_server_error;
+
+static DOCUMENT: LazyLock<String> = LazyLock::new(|| openapi::document().to_string());
+
+async fn handler(_req: Request) -> Result<Response<Body>, Error> {
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(CONTENT_TYPE, ContentType::Json.response_type())
+        .body(DOCUMENT.as_str().into())
+        .map_err(|e| internal_server_error(Box::new(e)))
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> Result<(), Error> {
+    tracing_subscriber::fmt().with_ansi(false).init();
+    vercel_runtime::run(handler).await
+}
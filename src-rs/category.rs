@@ -3,14 +3,19 @@ use std::collections::HashMap;
 use std::str::FromStr;
 
 use strum::EnumProperty;
-use strum_macros::{EnumProperty, EnumString};
-use tokei::Language;
+use strum_macros::{EnumIter, EnumProperty, EnumString, IntoStaticStr};
+use tokei::{Language, Report};
 
-#[derive(PartialEq, Eq, Debug, EnumString, EnumProperty)]
-#[strum(ascii_case_insensitive)]
+use crate::params;
+
+#[derive(
+    PartialEq, Eq, Debug, Default, Clone, Copy, EnumString, EnumProperty, EnumIter, IntoStaticStr,
+)]
+#[strum(ascii_case_insensitive, serialize_all = "lowercase")]
 pub enum Category {
     #[strum(props(Description = "blank lines"))]
     Blanks,
+    #[default]
     #[strum(props(Description = "total lines"))]
     Lines,
     #[strum(props(Description = "lines of code"))]
@@ -28,12 +33,22 @@ impl Category {
     }
 
     pub fn from_query(query: &HashMap<String, Cow<str>>) -> Result<Self, &'static str> {
-        match query.get("category") {
+        match params::CATEGORY.get(query) {
             Some(format) => Self::from_str(format).map_err(|_| {
                 "Invalid category parameter. Choices are 'code', 'lines', 'files', 'blanks', and \
                  'comments'"
             }),
-            None => Ok(Self::Lines),
+            None => Ok(Self::default()),
+        }
+    }
+
+    pub fn sort_from_query(query: &HashMap<String, Cow<str>>) -> Result<Self, &'static str> {
+        match params::SORT.get(query) {
+            Some(sort) => Self::from_str(sort).map_err(|_| {
+                "Invalid sort parameter. Choices are 'code', 'lines', 'files', 'blanks', and \
+                 'comments'"
+            }),
+            None => Ok(Self::default()),
         }
     }
 
@@ -46,6 +61,17 @@ impl Category {
             Self::Code => language.code,
         }
     }
+
+    pub fn report_stats(&self, report: &Report) -> usize {
+        let stats = report.stats.summarise();
+        match self {
+            Self::Blanks => stats.blanks,
+            Self::Files => 1,
+            Self::Comments => stats.comments,
+            Self::Lines => stats.lines(),
+            Self::Code => stats.code,
+        }
+    }
 }
 
 fn calc_files(language: &Language) -> usize {
@@ -5,10 +5,12 @@ use std::collections::HashMap;
 use std::fmt::Display;
 use std::str::FromStr;
 
-use strum::EnumProperty;
-use strum_macros::{EnumProperty, EnumString};
+use strum::{EnumProperty, IntoEnumIterator};
+use strum_macros::{EnumIter, EnumProperty, EnumString, IntoStaticStr};
 
-#[derive(PartialEq, Eq, Debug, EnumString, EnumProperty)]
+use crate::params::Param;
+
+#[derive(PartialEq, Eq, Debug, EnumString, EnumProperty, EnumIter, IntoStaticStr)]
 #[strum(ascii_case_insensitive, serialize_all = "lowercase")]
 pub enum Color {
     #[strum(props(Hex = "#4c1"))]
@@ -34,8 +36,8 @@ pub enum Color {
 }
 
 impl Color {
-    pub fn from_query(query: &HashMap<String, Cow<str>>, key: &str, default: Color) -> Self {
-        match query.get(key) {
+    pub fn from_query(query: &HashMap<String, Cow<str>>, param: &Param, default: Color) -> Self {
+        match param.get(query) {
             Some(color) => Self::from_str(color).unwrap_or_else(|_| {
                 let mut color = color.to_string();
                 let re = lazy_regex::regex!(r"^([\da-f]{3}){1,2}$");
@@ -48,6 +50,11 @@ impl Color {
             None => default,
         }
     }
+
+    /// All named colors, excluding arbitrary CSS colors.
+    pub fn named() -> impl Iterator<Item = Self> {
+        Self::iter().filter(|c| !matches!(c, Self::Other(_)))
+    }
 }
 
 impl Display for Color {
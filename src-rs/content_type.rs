@@ -1,30 +1,163 @@
 use std::borrow::Cow;
+use std::cmp::Reverse;
 use std::collections::HashMap;
 use std::str::FromStr;
 
 use strum::EnumProperty;
-use strum_macros::{EnumProperty, EnumString};
+use strum_macros::{EnumIter, EnumProperty, EnumString, IntoStaticStr};
 
-#[derive(PartialEq, Eq, Debug, EnumString, EnumProperty)]
-#[strum(ascii_case_insensitive)]
+use crate::params;
+
+#[derive(
+    PartialEq, Eq, Debug, Default, Clone, Copy, EnumString, EnumProperty, EnumIter, IntoStaticStr,
+)]
+#[strum(ascii_case_insensitive, serialize_all = "lowercase")]
 pub enum ContentType {
+    #[default]
     #[strum(props(ResponseType = "image/svg+xml"))]
     Svg,
     #[strum(props(ResponseType = "application/json"))]
     Json,
+    #[strum(props(ResponseType = "text/plain; charset=utf-8"))]
+    Text,
+    #[strum(props(ResponseType = "text/plain; version=0.0.4; charset=utf-8"))]
+    Prometheus,
+    #[strum(props(ResponseType = "text/csv; charset=utf-8"))]
+    Csv,
+    #[strum(props(ResponseType = "text/markdown; charset=utf-8"))]
+    Markdown,
+    #[strum(props(ResponseType = "application/json"))]
+    Files,
+    #[strum(props(ResponseType = "image/png"))]
+    Png,
+    #[strum(props(ResponseType = "text/html; charset=utf-8"))]
+    Html,
 }
 
+// Formats that can be selected through the Accept header, in order of preference
+const NEGOTIABLE: [ContentType; 5] = [
+    ContentType::Svg,
+    ContentType::Json,
+    ContentType::Text,
+    ContentType::Csv,
+    ContentType::Png,
+];
+
 impl ContentType {
     pub fn from_query(query: &HashMap<String, Cow<str>>) -> Result<Self, &'static str> {
-        match query.get("format") {
-            Some(format) => Self::from_str(format)
-                .map_err(|_| "Invalid format parameter. Choices are 'svg' and 'json'"),
-            None => Ok(Self::Svg),
+        match params::FORMAT.get(query) {
+            Some(format) => Self::from_str(format).map_err(|_| {
+                "Invalid format parameter. Choices are 'svg', 'json', 'text', 'prometheus', 'csv', \
+                 'markdown', 'files', 'png', and 'html'"
+            }),
+            None => Ok(Self::default()),
         }
     }
 
+    /// Picks the most preferred format from an `Accept` header. Returns `None` if none of the
+    /// requested media types are supported.
+    pub fn from_accept(accept: &str) -> Option<Self> {
+        let ranges: Vec<(&str, f32)> = accept
+            .split(',')
+            .filter_map(|range| {
+                let mut parts = range.split(';').map(str::trim);
+                let media_type = parts.next().filter(|m| !m.is_empty())?;
+                let quality = parts
+                    .find_map(|p| p.strip_prefix("q="))
+                    .map(|q| q.parse().unwrap_or(0.0))
+                    .unwrap_or(1.0);
+                Some((media_type, quality))
+            })
+            .collect();
+        if ranges.is_empty() {
+            return Some(Self::default());
+        }
+
+        // Each format takes the quality of the most specific range that matches it, so a range
+        // with a quality of 0 excludes a format even if a wildcard would allow it
+        NEGOTIABLE
+            .into_iter()
+            .filter_map(|content_type| {
+                let (position, quality) = ranges
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(position, (media_type, quality))| {
+                        Some((content_type.specificity(media_type)?, position, *quality))
+                    })
+                    .max_by_key(|(specificity, position, _)| (*specificity, Reverse(*position)))
+                    .map(|(_, position, quality)| (position, quality))?;
+                (quality > 0.0).then_some((content_type, position, quality))
+            })
+            // Prefer the highest quality, then the range listed first, then the order above
+            .min_by(|(_, a_position, a_quality), (_, b_position, b_quality)| {
+                b_quality
+                    .total_cmp(a_quality)
+                    .then(a_position.cmp(b_position))
+            })
+            .map(|(content_type, _, _)| content_type)
+    }
+
     pub fn response_type(&self) -> &str {
         self.get_str("ResponseType")
             .expect("ResponseType should be set")
     }
+
+    /// How specifically `media_type` matches this format, from `*/*` (0) to an exact match (2).
+    /// Returns `None` if it doesn't match.
+    fn specificity(&self, media_type: &str) -> Option<u8> {
+        let response_type = self.response_type().split(';').next().unwrap_or_default();
+        match media_type.split_once('/') {
+            Some(("*", "*")) => Some(0),
+            Some((main_type, "*")) => response_type
+                .split_once('/')
+                .is_some_and(|(t, _)| t.eq_ignore_ascii_case(main_type))
+                .then_some(1),
+            _ => response_type.eq_ignore_ascii_case(media_type).then_some(2),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_accept_without_ranges_uses_default() {
+        assert_eq!(ContentType::from_accept(""), Some(ContentType::Svg));
+        assert_eq!(ContentType::from_accept(" , "), Some(ContentType::Svg));
+    }
+
+    #[test]
+    fn from_accept_rejects_zero_quality() {
+        assert_eq!(ContentType::from_accept("image/svg+xml;q=0"), None);
+        assert_eq!(ContentType::from_accept("*/*;q=0"), None);
+        assert_eq!(
+            ContentType::from_accept("image/svg+xml;q=0, */*"),
+            Some(ContentType::Json)
+        );
+        assert_eq!(
+            ContentType::from_accept("*/*, image/*;q=0"),
+            Some(ContentType::Json)
+        );
+        assert_eq!(
+            ContentType::from_accept("image/*;q=0, image/png"),
+            Some(ContentType::Png)
+        );
+    }
+
+    #[test]
+    fn from_accept_picks_highest_quality() {
+        assert_eq!(
+            ContentType::from_accept("image/svg+xml;q=0.5, image/png"),
+            Some(ContentType::Png)
+        );
+        assert_eq!(
+            ContentType::from_accept("text/html, image/*;q=0.8"),
+            Some(ContentType::Svg)
+        );
+        assert_eq!(
+            ContentType::from_accept("application/json, image/svg+xml"),
+            Some(ContentType::Json)
+        );
+    }
 }
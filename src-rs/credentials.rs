@@ -0,0 +1,252 @@
+use std::cell::Cell;
+use std::env;
+use std::path::Path;
+
+use git2::{Cred, CredentialType, RemoteCallbacks};
+use tracing::warn;
+
+const CREDENTIALS_VAR: &str = "TOKEI_CREDENTIALS";
+const SSH_KEY_VAR: &str = "TOKEI_SSH_KEY";
+const SSH_KEY_PASSPHRASE_VAR: &str = "TOKEI_SSH_KEY_PASSPHRASE";
+// Accepted by GitHub, and ignored by most other forges when authenticating with a token
+const DEFAULT_USERNAME: &str = "x-access-token";
+
+/// An access token configured by the operator for a host, optionally narrowed to a single owner,
+/// or the deploy key used for every SSH remote.
+// Debug is intentionally not derived so secrets can't end up in the logs
+pub struct Credential {
+    pub host: String,
+    pub owner: Option<String>,
+    secret: Secret,
+}
+
+enum Secret {
+    Token {
+        username: String,
+        token: String,
+    },
+    /// Either the contents of a private key or a path to one.
+    SshKey {
+        key: String,
+        passphrase: Option<String>,
+    },
+}
+
+impl Credential {
+    /// Identifies which repositories this credential applies to. Used to keep cached results
+    /// for private repositories separate from everything else.
+    pub fn scope(&self) -> String {
+        if let Secret::SshKey { .. } = self.secret {
+            return "ssh".to_owned();
+        }
+        match &self.owner {
+            Some(owner) => format!("{}/{}", self.host, owner).to_ascii_lowercase(),
+            None => self.host.to_ascii_lowercase(),
+        }
+    }
+
+    pub fn remote_callbacks(&self) -> RemoteCallbacks<'_> {
+        let mut callbacks = RemoteCallbacks::new();
+        let attempted = Cell::new(false);
+        callbacks.credentials(move |_url, username, allowed_types| {
+            // libgit2 keeps asking for credentials until it gets a valid one, so bail out
+            // instead of retrying a rejected token forever
+            if attempted.replace(true) {
+                return Err(git2::Error::new(
+                    git2::ErrorCode::Auth,
+                    git2::ErrorClass::Http,
+                    "configured credentials were rejected",
+                ));
+            }
+            match &self.secret {
+                Secret::Token { username, token } => {
+                    if !allowed_types.contains(CredentialType::USER_PASS_PLAINTEXT) {
+                        return Err(git2::Error::from_str(
+                            "remote does not support token authentication",
+                        ));
+                    }
+                    Cred::userpass_plaintext(username, token)
+                }
+                Secret::SshKey { key, passphrase } => {
+                    if !allowed_types.contains(CredentialType::SSH_KEY) {
+                        return Err(git2::Error::from_str(
+                            "remote does not support key authentication",
+                        ));
+                    }
+                    // The username is always part of the SSH URLs built for forges
+                    let username = username.unwrap_or("git");
+                    if key.trim_start().starts_with("-----BEGIN") {
+                        Cred::ssh_key_from_memory(username, None, key, passphrase.as_deref())
+                    } else {
+                        Cred::ssh_key(username, None, Path::new(key), passphrase.as_deref())
+                    }
+                }
+            }
+        });
+        callbacks
+    }
+
+    fn parse(entry: &str) -> Option<Self> {
+        let (scope, secret) = entry.split_once('=')?;
+        let (host, owner) = match scope.split_once('/') {
+            Some((host, owner)) => (host, Some(owner.to_owned())),
+            None => (scope, None),
+        };
+        let (username, token) = secret.split_once(':').unwrap_or((DEFAULT_USERNAME, secret));
+        if host.is_empty() || token.is_empty() || owner.as_ref().is_some_and(|o| o.is_empty()) {
+            return None;
+        }
+        Some(Self {
+            host: host.to_owned(),
+            owner,
+            secret: Secret::Token {
+                username: username.to_owned(),
+                token: token.to_owned(),
+            },
+        })
+    }
+}
+
+#[derive(Default)]
+pub struct Credentials {
+    tokens: Vec<Credential>,
+    ssh_key: Option<Credential>,
+}
+
+impl Credentials {
+    /// Reads credentials from `TOKEI_CREDENTIALS`, a comma or whitespace separated list of
+    /// `host[/owner]=[username:]token` entries. The deploy key for SSH remotes is read from
+    /// `TOKEI_SSH_KEY`, which can contain either the private key itself or a path to it, and
+    /// `TOKEI_SSH_KEY_PASSPHRASE`.
+    pub fn from_env() -> Self {
+        let mut credentials = match env::var(CREDENTIALS_VAR) {
+            Ok(value) => Self::parse(&value),
+            Err(_) => Self::default(),
+        };
+        if let Ok(key) = env::var(SSH_KEY_VAR).map(|k| k.trim().to_owned())
+            && !key.is_empty()
+        {
+            credentials = credentials.with_ssh_key(
+                key,
+                env::var(SSH_KEY_PASSPHRASE_VAR)
+                    .ok()
+                    .filter(|p| !p.is_empty()),
+            );
+        }
+        credentials
+    }
+
+    pub fn with_ssh_key(mut self, key: String, passphrase: Option<String>) -> Self {
+        self.ssh_key = Some(Credential {
+            host: String::new(),
+            owner: None,
+            secret: Secret::SshKey { key, passphrase },
+        });
+        self
+    }
+
+    pub fn parse(value: &str) -> Self {
+        let credentials = value
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|entry| !entry.is_empty())
+            .filter_map(|entry| {
+                let credential = Credential::parse(entry);
+                if credential.is_none() {
+                    // Don't log the entry itself since it probably contains a token
+                    warn!("Ignoring invalid entry in {CREDENTIALS_VAR}");
+                }
+                credential
+            })
+            .collect();
+        Self {
+            tokens: credentials,
+            ssh_key: None,
+        }
+    }
+
+    /// Finds the credential for a repository, preferring one scoped to the owner over one for
+    /// the whole host.
+    pub fn find(&self, host: &str, owner: &str) -> Option<&Credential> {
+        let host_matches = |c: &&Credential| c.host.eq_ignore_ascii_case(host);
+        self.tokens
+            .iter()
+            .filter(host_matches)
+            .find(|c| {
+                c.owner
+                    .as_ref()
+                    .is_some_and(|o| o.eq_ignore_ascii_case(owner))
+            })
+            .or_else(|| {
+                self.tokens
+                    .iter()
+                    .filter(host_matches)
+                    .find(|c| c.owner.is_none())
+            })
+    }
+
+    /// The deploy key used to authenticate with SSH remotes.
+    pub fn ssh_key(&self) -> Option<&Credential> {
+        self.ssh_key.as_ref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn token(credential: &Credential) -> (&str, &str) {
+        match &credential.secret {
+            Secret::Token { username, token } => (username, token),
+            Secret::SshKey { .. } => panic!("expected a token"),
+        }
+    }
+
+    #[test]
+    fn parses_entries() {
+        let credentials = Credentials::parse(
+            "github.com=ghp_host, github.com/My-Org=bot:ghp_org\ngitlab.example.com=oauth2:glpat",
+        );
+        assert_eq!(credentials.tokens.len(), 3);
+
+        let host = credentials.find("GitHub.com", "someone").unwrap();
+        assert_eq!(host.owner, None);
+        assert_eq!(token(host), (DEFAULT_USERNAME, "ghp_host"));
+        assert_eq!(host.scope(), "github.com");
+
+        let org = credentials.find("github.com", "my-org").unwrap();
+        assert_eq!(token(org), ("bot", "ghp_org"));
+        assert_eq!(org.scope(), "github.com/my-org");
+
+        let gitlab = credentials.find("gitlab.example.com", "group").unwrap();
+        assert_eq!(token(gitlab), ("oauth2", "glpat"));
+        assert!(credentials.find("codeberg.org", "owner").is_none());
+    }
+
+    #[test]
+    fn owner_credential_is_not_used_for_other_owners() {
+        let credentials = Credentials::parse("github.com/my-org=ghp_org");
+        assert!(credentials.find("github.com", "other").is_none());
+    }
+
+    #[test]
+    fn tokens_can_contain_colons_and_equals() {
+        let credentials = Credentials::parse("git.example.com=user:to:ken=");
+        let credential = credentials.find("git.example.com", "owner").unwrap();
+        assert_eq!(token(credential), ("user", "to:ken="));
+    }
+
+    #[test]
+    fn skips_invalid_entries() {
+        let credentials =
+            Credentials::parse("missing-token, =token, host=, host/=token, host=user:");
+        assert!(credentials.tokens.is_empty());
+    }
+
+    #[test]
+    fn ssh_key_has_its_own_scope() {
+        let credentials =
+            Credentials::parse("github.com=ghp").with_ssh_key("/keys/deploy".to_owned(), None);
+        assert_eq!(credentials.ssh_key().unwrap().scope(), "ssh");
+        assert!(credentials.find("", "").is_none());
+    }
+}
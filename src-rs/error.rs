@@ -0,0 +1,192 @@
+use std::fmt::{self, Debug, Display};
+use std::time::Duration;
+
+use git2::{ErrorClass, ErrorCode};
+use http::StatusCode;
+use serde_json::{Value, json};
+use tracing::{error, warn};
+
+#[derive(Debug)]
+pub enum ApiError {
+    InvalidParameter(&'static str),
+    UnknownLanguage(String),
+    NotAcceptable,
+    InvalidRepo(String),
+    RepoNotFound(String),
+    BranchNotFound(String),
+    Unauthorized(String),
+    Forbidden(String),
+    UpstreamUnavailable(String),
+    CloneFailed(String),
+    RepoTooLarge { limit_bytes: usize },
+    Timeout { limit: Duration },
+    Unavailable { retry_after: Duration },
+    Internal,
+}
+
+impl ApiError {
+    /// Logs the underlying error and hides the details from the response.
+    pub fn internal(err: impl Debug) -> Self {
+        error!("{err:?}");
+        Self::Internal
+    }
+
+    /// Maps a git error to the matching HTTP error. Returns `None` if the error doesn't fit any
+    /// known category so the caller can choose a fallback.
+    pub fn from_git(context: &str, err: &git2::Error) -> Option<Self> {
+        let message = format!("{context}: {}", err.message());
+        let http_status = match err.class() {
+            ErrorClass::Http => parse_http_status(err.message()),
+            _ => None,
+        };
+        let classified = match (err.code(), err.class(), http_status) {
+            (ErrorCode::NotFound, _, _) | (_, _, Some(404 | 410)) => Self::RepoNotFound(message),
+            (ErrorCode::Auth, _, _) | (_, _, Some(401)) => Self::Unauthorized(message),
+            (_, _, Some(403)) => Self::Forbidden(message),
+            (ErrorCode::Certificate, _, _)
+            | (_, ErrorClass::Net | ErrorClass::Ssl | ErrorClass::Ssh, _)
+            | (_, _, Some(500..=599)) => Self::UpstreamUnavailable(message),
+            (ErrorCode::InvalidSpec | ErrorCode::Invalid | ErrorCode::Ambiguous, _, _)
+            | (_, ErrorClass::Invalid | ErrorClass::Reference, _) => Self::InvalidRepo(message),
+            (ErrorCode::GenericError, ErrorClass::Os | ErrorClass::Filesystem, _) => {
+                Self::internal(err)
+            }
+            _ => return None,
+        };
+        warn!(
+            "Classified git error (code: {:?}, class: {:?}) as {}: {err}",
+            err.code(),
+            err.class(),
+            classified.status()
+        );
+        Some(classified)
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Self::InvalidParameter(_) | Self::UnknownLanguage(_) | Self::InvalidRepo(_) => {
+                StatusCode::BAD_REQUEST
+            }
+            Self::NotAcceptable => StatusCode::NOT_ACCEPTABLE,
+            Self::RepoNotFound(_) | Self::BranchNotFound(_) => StatusCode::NOT_FOUND,
+            Self::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            Self::Forbidden(_) => StatusCode::FORBIDDEN,
+            Self::CloneFailed(_) | Self::UpstreamUnavailable(_) => StatusCode::BAD_GATEWAY,
+            Self::RepoTooLarge { .. } => StatusCode::PAYLOAD_TOO_LARGE,
+            Self::Timeout { .. } => StatusCode::GATEWAY_TIMEOUT,
+            Self::Unavailable { .. } => StatusCode::SERVICE_UNAVAILABLE,
+            Self::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// Stable identifier for the kind of error.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::InvalidParameter(_) => "invalid_parameter",
+            Self::UnknownLanguage(_) => "unknown_language",
+            Self::NotAcceptable => "not_acceptable",
+            Self::InvalidRepo(_) => "invalid_repo",
+            Self::RepoNotFound(_) => "repo_not_found",
+            Self::BranchNotFound(_) => "branch_not_found",
+            Self::Unauthorized(_) => "unauthorized",
+            Self::Forbidden(_) => "forbidden",
+            Self::UpstreamUnavailable(_) => "upstream_unavailable",
+            Self::CloneFailed(_) => "clone_failed",
+            Self::RepoTooLarge { .. } => "repo_too_large",
+            Self::Timeout { .. } => "timeout",
+            Self::Unavailable { .. } => "unavailable",
+            Self::Internal => "internal",
+        }
+    }
+
+    /// Short description that fits on a badge.
+    pub fn summary(&self) -> &'static str {
+        match self {
+            Self::InvalidParameter(_) => "invalid parameter",
+            Self::UnknownLanguage(_) => "unknown language",
+            Self::NotAcceptable => "not acceptable",
+            Self::InvalidRepo(_) => "invalid repo",
+            Self::RepoNotFound(_) => "repo not found",
+            Self::BranchNotFound(_) => "branch not found",
+            Self::Unauthorized(_) => "unauthorized",
+            Self::Forbidden(_) => "forbidden",
+            Self::UpstreamUnavailable(_) => "host unreachable",
+            Self::CloneFailed(_) => "clone failed",
+            Self::RepoTooLarge { .. } => "repo too large",
+            Self::Timeout { .. } => "timed out",
+            Self::Unavailable { .. } => "unavailable",
+            Self::Internal => "error",
+        }
+    }
+
+    /// How long clients should wait before retrying, for errors that are expected to clear up on
+    /// their own.
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            Self::Unavailable { retry_after } => Some(*retry_after),
+            _ => None,
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "error": self.code(),
+            "message": self.to_string(),
+            "status": self.status().as_u16(),
+        })
+    }
+}
+
+impl Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidParameter(message) => f.write_str(message),
+            Self::UnknownLanguage(language) => write!(
+                f,
+                "Unknown language: {language}. Please note that languages are case sensitive and \
+                 should be capitalized."
+            ),
+            Self::NotAcceptable => f.write_str(
+                "None of the requested media types are supported. Choices are 'image/svg+xml', \
+                 'application/json', 'text/plain', 'text/csv', and 'image/png'",
+            ),
+            Self::InvalidRepo(message) => write!(f, "Invalid repository: {message}"),
+            Self::RepoNotFound(message)
+            | Self::Unauthorized(message)
+            | Self::Forbidden(message)
+            | Self::UpstreamUnavailable(message) => f.write_str(message),
+            Self::BranchNotFound(branch) => write!(f, "Requested branch {branch:?} not found"),
+            Self::CloneFailed(message) => write!(f, "Error cloning repository: {message}"),
+            Self::RepoTooLarge { limit_bytes } => write!(
+                f,
+                "Repository exceeds the maximum download size of {limit_bytes} bytes"
+            ),
+            Self::Timeout { limit } => write!(
+                f,
+                "Repository took longer than {} seconds to download",
+                limit.as_secs()
+            ),
+            Self::Unavailable { retry_after } => write!(
+                f,
+                "The service is temporarily unavailable. Please retry in {} seconds",
+                retry_after.as_secs()
+            ),
+            Self::Internal => f.write_str("Internal Server Error"),
+        }
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+// libgit2 doesn't expose the response status directly, but includes it in the error message
+// (ex: "unexpected http status code: 404").
+fn parse_http_status(message: &str) -> Option<u16> {
+    let (_, status) = message.rsplit_once("status code: ")?;
+    status.get(..3)?.parse().ok()
+}
+
+impl From<&'static str> for ApiError {
+    fn from(message: &'static str) -> Self {
+        Self::InvalidParameter(message)
+    }
+}
@@ -0,0 +1,437 @@
+use std::cmp::Reverse;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::{env, io};
+
+use git2::build::CheckoutBuilder;
+use git2::{ObjectType, Oid, Repository, Tree, TreeWalkMode, TreeWalkResult};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use tokei::{Config, Language, LanguageType, Languages, Report};
+use tracing::{info, warn};
+
+const FETCH_STRATEGY_VAR: &str = "TOKEI_FETCH_STRATEGY";
+// tokei reads these to exclude files, so they're always needed. Later entries take precedence
+// over earlier ones, wherever they are in the tree.
+const IGNORE_FILES: [&str; 3] = [".gitignore", ".ignore", ".tokeignore"];
+const SYMLINK_MODE: i32 = 0o120000;
+// Far longer than any real interpreter line, and shorter than the smallest pipe buffer
+const MAX_SHEBANG_LEN: usize = 1024;
+
+/// How a repository's files are made available to tokei after fetching.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FetchStrategy {
+    /// Check out every file in the repository.
+    #[default]
+    Checkout,
+    /// Only check out files that tokei can count, skipping assets like images and binaries.
+    Sparse,
+    /// Count files straight from the fetched objects without checking anything out.
+    Memory,
+}
+
+impl FetchStrategy {
+    /// Reads `TOKEI_FETCH_STRATEGY`, which can be `checkout`, `sparse`, or `memory`.
+    pub fn from_env() -> Self {
+        match env::var(FETCH_STRATEGY_VAR).as_deref() {
+            Ok("checkout") | Err(_) => Self::Checkout,
+            Ok("sparse") => Self::Sparse,
+            Ok("memory") => Self::Memory,
+            Ok(value) => {
+                warn!("Ignoring invalid value for {FETCH_STRATEGY_VAR}: {value}");
+                Self::default()
+            }
+        }
+    }
+
+    /// Whether the clone should skip checking out the working tree so it can be populated
+    /// afterwards.
+    pub fn skip_checkout(self) -> bool {
+        self != Self::Checkout
+    }
+}
+
+/// Checks out the files in `HEAD` that map to a language allowed by `config`, along with any
+/// ignore files so tokei's excludes still apply. The repository must have been cloned without a
+/// checkout.
+pub fn sparse_checkout(repo: &Repository, config: &Config) -> Result<(), git2::Error> {
+    let tree = repo.head()?.peel_to_tree()?;
+    let workdir = workdir(repo)?;
+
+    let mut checkout = CheckoutBuilder::new();
+    checkout.force().disable_pathspec_match(true);
+    let mut selected = 0;
+    for (path, _) in visible_blobs(&tree, config)? {
+        if is_ignore_file(&path) || is_countable(&workdir.join(&path), config) {
+            checkout.path(path);
+            selected += 1;
+        }
+    }
+
+    info!("Checking out {selected} files");
+    // An empty path list would check out everything
+    if selected == 0 {
+        return Ok(());
+    }
+    repo.checkout_tree(tree.as_object(), Some(&mut checkout))
+}
+
+/// Counts the files in `HEAD` by reading blobs from the object database instead of a working
+/// tree. Ignore files are applied the same way tokei applies them on disk, so the results match
+/// counting a checkout. The repository must have been cloned without a checkout.
+pub fn count_in_memory(repo: &Repository, config: &Config) -> Result<Language, git2::Error> {
+    let tree = repo.head()?.peel_to_tree()?;
+    let workdir = workdir(repo)?;
+    let blobs = visible_blobs(&tree, config)?;
+    let ignores = Ignores::from_blobs(repo, &blobs)?;
+
+    let mut languages = Languages::new();
+    for (path, oid) in blobs {
+        if ignores.is_ignored(&path) {
+            continue;
+        }
+        // Nothing is checked out, so this only matches on the file name
+        let language = match LanguageType::from_path(workdir.join(&path), config) {
+            Some(language) => language,
+            None if path.extension().is_none() => {
+                let blob = repo.find_blob(oid)?;
+                match detect_shebang(blob.content()) {
+                    Ok(Some(language)) => language,
+                    Ok(None) => continue,
+                    Err(e) => {
+                        warn!("Error detecting language of {path:?}: {e:?}");
+                        continue;
+                    }
+                }
+            }
+            None => continue,
+        };
+        if config
+            .types
+            .as_ref()
+            .is_some_and(|types| !types.contains(&language))
+        {
+            continue;
+        }
+
+        let blob = repo.find_blob(oid)?;
+        let mut report = Report::new(path);
+        report.stats = language.parse_from_slice(blob.content(), config);
+        languages.entry(language).or_default().add_report(report);
+    }
+    for language in languages.values_mut() {
+        language.total();
+    }
+    Ok(languages.total())
+}
+
+fn workdir(repo: &Repository) -> Result<&Path, git2::Error> {
+    repo.workdir()
+        .ok_or_else(|| git2::Error::from_str("repository has no working directory"))
+}
+
+/// Lists the blobs in a tree that tokei would walk, skipping symlinks and, unless `config` says
+/// otherwise, hidden files.
+fn visible_blobs(tree: &Tree, config: &Config) -> Result<Vec<(PathBuf, Oid)>, git2::Error> {
+    let include_hidden = config.hidden.unwrap_or(false);
+    let mut blobs = Vec::new();
+    tree.walk(TreeWalkMode::PreOrder, |root, entry| {
+        let Some(name) = entry.name() else {
+            return TreeWalkResult::Skip;
+        };
+        let hidden = name.starts_with('.') && !IGNORE_FILES.contains(&name);
+        match entry.kind() {
+            Some(ObjectType::Tree) if hidden && !include_hidden => TreeWalkResult::Skip,
+            Some(ObjectType::Blob)
+                if entry.filemode() != SYMLINK_MODE && (include_hidden || !hidden) =>
+            {
+                blobs.push((PathBuf::from(format!("{root}{name}")), entry.id()));
+                TreeWalkResult::Ok
+            }
+            _ => TreeWalkResult::Ok,
+        }
+    })?;
+    Ok(blobs)
+}
+
+fn is_ignore_file(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| IGNORE_FILES.contains(&name))
+}
+
+/// Whether tokei might count the file at `path`. The file doesn't need to exist. Files without an
+/// extension are included since tokei may detect their language from a shebang.
+fn is_countable(path: &Path, config: &Config) -> bool {
+    match LanguageType::from_path(path, config) {
+        Some(language) => config
+            .types
+            .as_ref()
+            .is_none_or(|types| types.contains(&language)),
+        None => path.extension().is_none(),
+    }
+}
+
+/// tokei only reads shebangs from files, so hand it the first line through a pipe instead of
+/// writing it to disk.
+#[cfg(unix)]
+fn detect_shebang(content: &[u8]) -> io::Result<Option<LanguageType>> {
+    use std::os::fd::AsRawFd;
+
+    let Some(first_line) = shebang_line(content) else {
+        return Ok(None);
+    };
+    let (reader, mut writer) = io::pipe()?;
+    writer.write_all(first_line)?;
+    writer.write_all(b"\n")?;
+    // Close the write end so tokei sees the end of the line
+    drop(writer);
+    Ok(LanguageType::from_shebang(format!(
+        "/dev/fd/{}",
+        reader.as_raw_fd()
+    )))
+}
+
+/// Without `/dev/fd`, the first line has to be written to a temporary file.
+#[cfg(not(unix))]
+fn detect_shebang(content: &[u8]) -> io::Result<Option<LanguageType>> {
+    let Some(first_line) = shebang_line(content) else {
+        return Ok(None);
+    };
+    let mut file = tempfile::Builder::new()
+        .prefix("tokei-shebang")
+        .tempfile()?;
+    file.write_all(first_line)?;
+    file.write_all(b"\n")?;
+    Ok(LanguageType::from_shebang(file.path()))
+}
+
+/// The first line of `content` if it's a shebang, truncated so it always fits in a pipe's
+/// buffer.
+fn shebang_line(content: &[u8]) -> Option<&[u8]> {
+    if !content.starts_with(b"#!") {
+        return None;
+    }
+    let first_line = content.split(|b| *b == b'\n').next().unwrap_or_default();
+    Some(&first_line[..first_line.len().min(MAX_SHEBANG_LEN)])
+}
+
+/// The ignore files found in a tree, ordered the way the `ignore` crate checks them: by file
+/// name first, then with the most specific directory first.
+struct Ignores(Vec<(PathBuf, Gitignore)>);
+
+impl Ignores {
+    fn from_blobs(repo: &Repository, blobs: &[(PathBuf, Oid)]) -> Result<Self, git2::Error> {
+        let mut ignores = Vec::new();
+        for (path, oid) in blobs.iter() {
+            let Some(priority) = IGNORE_FILES
+                .iter()
+                .position(|name| path.file_name().is_some_and(|n| n == *name))
+            else {
+                continue;
+            };
+            let dir = path.parent().unwrap_or(Path::new("")).to_owned();
+            let blob = repo.find_blob(*oid)?;
+            let mut builder = GitignoreBuilder::new(&dir);
+            for line in String::from_utf8_lossy(blob.content()).lines() {
+                // Invalid patterns are skipped, same as when tokei reads the file from disk
+                let _ = builder.add_line(Some(path.clone()), line);
+            }
+            match builder.build() {
+                Ok(gitignore) => ignores.push((dir, priority, gitignore)),
+                Err(e) => warn!("Error reading ignore file {path:?}: {e:?}"),
+            }
+        }
+        ignores.sort_by_key(|(dir, priority, _)| Reverse((*priority, dir.components().count())));
+        Ok(Self(
+            ignores
+                .into_iter()
+                .map(|(dir, _, gitignore)| (dir, gitignore))
+                .collect(),
+        ))
+    }
+
+    /// tokei doesn't descend into ignored directories, so a file is also ignored if any directory
+    /// above it is, even when a pattern whitelists the file itself.
+    fn is_ignored(&self, path: &Path) -> bool {
+        path.ancestors()
+            .skip(1)
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .any(|dir| self.matched(dir, true))
+            || self.matched(path, false)
+    }
+
+    fn matched(&self, path: &Path, is_dir: bool) -> bool {
+        for (dir, gitignore) in &self.0 {
+            // An ignore file applies to the entries below its directory, not the directory itself
+            if path == dir || !path.starts_with(dir) {
+                continue;
+            }
+            let matched = gitignore.matched(path, is_dir);
+            if matched.is_ignore() {
+                return true;
+            }
+            if matched.is_whitelist() {
+                return false;
+            }
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use git2::build::RepoBuilder;
+    use git2::{IndexAddOption, Signature};
+    use tempfile::TempDir;
+
+    use super::*;
+
+    const FILES: [(&str, &str); 15] = [
+        ("src/main.rs", "// Entry point\nfn main() {}\n\n"),
+        (".gitignore", "generated/\nvendor/*\n*.py\n"),
+        (
+            ".tokeignore",
+            "!vendor/own.rs\n!generated/keep.rs\n!lib/shared.rs\n",
+        ),
+        ("generated/keep.rs", "fn keep() {}\n"),
+        ("generated/drop.rs", "fn drop() {}\n"),
+        ("vendor/own.rs", "fn own() {}\n"),
+        ("vendor/dep.rs", "fn dep() {}\n"),
+        ("lib/.gitignore", "*.rs\n"),
+        ("lib/.tokeignore", "!keep.py\n"),
+        ("lib/shared.rs", "fn shared() {}\n"),
+        ("lib/keep.py", "# Kept\nprint(1)\n"),
+        ("lib/other.py", "print(2)\n"),
+        (".hidden/secret.rs", "fn secret() {}\n"),
+        ("tool", "#!/usr/bin/env python3\nprint(3)\n"),
+        ("logo.png", "\u{89}PNG\r\n"),
+    ];
+    const COUNTED: [&str; 5] = [
+        "lib/keep.py",
+        "lib/shared.rs",
+        "src/main.rs",
+        "tool",
+        "vendor/own.rs",
+    ];
+
+    /// Commits `FILES` to a new repository, and returns it with a clone that has nothing checked
+    /// out.
+    fn repos() -> (TempDir, TempDir, Repository) {
+        let source = TempDir::new().unwrap();
+        let repo = Repository::init(source.path()).unwrap();
+        for (path, content) in FILES {
+            let path = source.path().join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        }
+        #[cfg(unix)]
+        std::os::unix::fs::symlink("src/main.rs", source.path().join("link.rs")).unwrap();
+
+        let mut index = repo.index().unwrap();
+        index.add_all(["*"], IndexAddOption::FORCE, None).unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let signature = Signature::now("tokei", "tokei@example.com").unwrap();
+        repo.commit(
+            Some("HEAD"),
+            &signature,
+            &signature,
+            "Add files",
+            &tree,
+            &[],
+        )
+        .unwrap();
+
+        let target = TempDir::new().unwrap();
+        let mut checkout = CheckoutBuilder::new();
+        checkout.dry_run();
+        let clone = RepoBuilder::new()
+            .with_checkout(checkout)
+            .clone(source.path().to_str().unwrap(), target.path())
+            .unwrap();
+        (source, target, clone)
+    }
+
+    fn count_checkout(path: &Path, config: &Config) -> Language {
+        let mut languages = Languages::new();
+        languages.get_statistics(&[path], &[], config);
+        let mut stats = languages.total();
+        for report in &mut stats.reports {
+            report.name = report.name.strip_prefix(path).unwrap().to_owned();
+        }
+        stats
+    }
+
+    fn files(stats: &Language) -> Vec<(PathBuf, usize, usize, usize)> {
+        let mut files: Vec<_> = stats
+            .reports
+            .iter()
+            .map(|r| {
+                (
+                    r.name.clone(),
+                    r.stats.code,
+                    r.stats.comments,
+                    r.stats.blanks,
+                )
+            })
+            .collect();
+        files.sort();
+        files
+    }
+
+    fn names(stats: &Language) -> Vec<PathBuf> {
+        files(stats).into_iter().map(|(name, ..)| name).collect()
+    }
+
+    #[test]
+    fn in_memory_matches_checkout() {
+        let (source, _target, clone) = repos();
+        let config = Config::default();
+        let in_memory = count_in_memory(&clone, &config).unwrap();
+        let checkout = count_checkout(source.path(), &config);
+
+        assert_eq!(names(&in_memory), COUNTED.map(PathBuf::from));
+        assert_eq!(files(&in_memory), files(&checkout));
+        assert_eq!(
+            (in_memory.code, in_memory.comments, in_memory.blanks),
+            (checkout.code, checkout.comments, checkout.blanks)
+        );
+    }
+
+    #[test]
+    fn in_memory_filters_languages() {
+        let (source, _target, clone) = repos();
+        let config = Config {
+            types: Some(vec![LanguageType::Rust]),
+            ..Config::default()
+        };
+        let in_memory = count_in_memory(&clone, &config).unwrap();
+        assert_eq!(
+            names(&in_memory),
+            ["lib/shared.rs", "src/main.rs", "vendor/own.rs"].map(PathBuf::from)
+        );
+        assert_eq!(
+            files(&in_memory),
+            files(&count_checkout(source.path(), &config))
+        );
+    }
+
+    #[test]
+    fn sparse_checkout_matches_checkout() {
+        let (source, target, clone) = repos();
+        let config = Config::default();
+        sparse_checkout(&clone, &config).unwrap();
+
+        for ignore_file in [".gitignore", ".tokeignore", "lib/.gitignore"] {
+            assert!(target.path().join(ignore_file).exists(), "{ignore_file}");
+        }
+        for skipped in ["logo.png", ".hidden/secret.rs", "link.rs"] {
+            assert!(!target.path().join(skipped).exists(), "{skipped}");
+        }
+        assert_eq!(
+            files(&count_checkout(target.path(), &config)),
+            files(&count_checkout(source.path(), &config))
+        );
+    }
+}
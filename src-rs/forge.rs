@@ -0,0 +1,349 @@
+use std::env;
+
+use tracing::warn;
+
+use crate::error::ApiError;
+use crate::forge_api::{ApiKind, ForgeApi};
+
+const FORGES_VAR: &str = "TOKEI_FORGES";
+const FORGE_APIS_VAR: &str = "TOKEI_FORGE_APIS";
+const DEFAULT_PATH: &str = "{user}/{repo}";
+const DEFAULT_SSH_USERNAME: &str = "git";
+const BUILTIN_FORGES: [(&str, &str, &str); 6] = [
+    ("github", "github.com", DEFAULT_PATH),
+    ("gitlab", "gitlab.com", DEFAULT_PATH),
+    ("bitbucket", "bitbucket.org", DEFAULT_PATH),
+    ("codeberg", "codeberg.org", DEFAULT_PATH),
+    ("sourcehut", "git.sr.ht", "~{user}/{repo}"),
+    ("gitea", "gitea.com", DEFAULT_PATH),
+];
+const BUILTIN_APIS: [(&str, ApiKind); 4] = [
+    ("github.com", ApiKind::GitHub),
+    ("gitlab.com", ApiKind::GitLab),
+    ("codeberg.org", ApiKind::Gitea),
+    ("gitea.com", ApiKind::Gitea),
+];
+
+/// Protocol used to clone repositories from a forge.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Transport {
+    #[default]
+    Https,
+    /// Authenticates with the operator's deploy key, if one is configured.
+    Ssh,
+    /// Unauthenticated `git daemon` protocol.
+    Git,
+}
+
+impl Transport {
+    fn from_scheme(scheme: &str) -> Option<Self> {
+        match scheme.to_ascii_lowercase().as_str() {
+            "https" => Some(Self::Https),
+            "ssh" => Some(Self::Ssh),
+            "git" => Some(Self::Git),
+            _ => None,
+        }
+    }
+}
+
+/// A git host along with the layout of its repository URLs.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Forge {
+    /// Host name, optionally followed by a port.
+    pub host: String,
+    pub transport: Transport,
+    /// User to connect as over SSH.
+    username: Option<String>,
+    /// API that can list files without cloning the repository.
+    pub api: Option<ForgeApi>,
+    /// Whether the API should be used by default when a request only needs file counts.
+    pub prefer_api: bool,
+    /// Path of a repository relative to the host, with `{user}` and `{repo}` placeholders.
+    path: String,
+}
+
+impl Forge {
+    pub fn new(host: impl Into<String>) -> Self {
+        Self {
+            host: host.into(),
+            transport: Transport::default(),
+            username: None,
+            api: None,
+            prefer_api: false,
+            path: DEFAULT_PATH.to_owned(),
+        }
+    }
+
+    /// `user` can be a nested namespace such as `group/subgroup`.
+    pub fn repo_path(&self, user: &str, repo: &str) -> String {
+        // Sourcehut users are prefixed with a tilde, but accept URLs that already include it
+        let user = if self.path.contains("~{user}") {
+            user.trim_start_matches('~')
+        } else {
+            user
+        };
+        self.path.replace("{user}", user).replace("{repo}", repo)
+    }
+
+    pub fn clone_url(&self, user: &str, repo: &str) -> String {
+        let (host, path) = (&self.host, self.repo_path(user, repo));
+        match self.transport {
+            Transport::Https => format!("https://{host}/{path}"),
+            Transport::Ssh => {
+                let username = self.username.as_deref().unwrap_or(DEFAULT_SSH_USERNAME);
+                format!("ssh://{username}@{host}/{path}")
+            }
+            Transport::Git => format!("git://{host}/{path}"),
+        }
+    }
+
+    fn parse(value: &str) -> Option<Self> {
+        let (transport, value) = match value.split_once("://") {
+            Some((scheme, rest)) => (Transport::from_scheme(scheme)?, rest),
+            None => (Transport::default(), value),
+        };
+        let (authority, path) = match value.split_once('/') {
+            Some((authority, path)) => (authority, path.to_owned()),
+            None => (value, DEFAULT_PATH.to_owned()),
+        };
+        let (username, host) = match authority.split_once('@') {
+            // Only SSH needs a username, other transports authenticate separately
+            Some((username, host)) if transport == Transport::Ssh && !username.is_empty() => {
+                (Some(username.to_owned()), host)
+            }
+            Some(_) => return None,
+            None => (None, authority),
+        };
+        if host.is_empty() || !path.contains("{user}") || !path.contains("{repo}") {
+            return None;
+        }
+        Some(Self {
+            username,
+            path,
+            transport,
+            ..Self::new(host.to_ascii_lowercase())
+        })
+    }
+}
+
+/// Maps the `domain` segment of a request to the forge that hosts the repository.
+pub struct ForgeRegistry {
+    aliases: Vec<(String, Forge)>,
+    /// APIs configured by the operator, keyed by host.
+    apis: Vec<(String, ForgeApi)>,
+}
+
+impl Default for ForgeRegistry {
+    fn default() -> Self {
+        let aliases = BUILTIN_FORGES
+            .iter()
+            .map(|(alias, host, path)| {
+                (
+                    alias.to_string(),
+                    Forge {
+                        path: path.to_string(),
+                        ..Forge::new(*host)
+                    },
+                )
+            })
+            .collect();
+        Self {
+            aliases,
+            apis: Vec::new(),
+        }
+    }
+}
+
+impl ForgeRegistry {
+    /// Adds aliases from `TOKEI_FORGES`, a comma or whitespace separated list of
+    /// `alias=[scheme://][username@]host[/path]` entries, to the built-in ones. The scheme can be
+    /// `https` (the default), `ssh`, or `git`, and the username is only used for SSH. The path
+    /// describes where repositories live on the host using `{user}` and `{repo}` placeholders.
+    ///
+    /// `TOKEI_FORGE_APIS` is a list of `host=kind` entries, where the kind is `github`, `gitlab`,
+    /// or `gitea`. Requests for these hosts use the API by default when they only need file
+    /// counts.
+    pub fn from_env() -> Self {
+        let registry = match env::var(FORGES_VAR) {
+            Ok(value) => Self::parse(&value),
+            Err(_) => Self::default(),
+        };
+        match env::var(FORGE_APIS_VAR) {
+            Ok(value) => registry.with_apis(&value),
+            Err(_) => registry,
+        }
+    }
+
+    pub fn with_apis(mut self, value: &str) -> Self {
+        let entries = value
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|entry| !entry.is_empty());
+        for entry in entries {
+            let parsed = entry
+                .split_once('=')
+                .filter(|(host, _)| !host.is_empty())
+                .and_then(|(host, kind)| {
+                    let host = host.to_ascii_lowercase();
+                    let api = ForgeApi::for_host(kind.parse().ok()?, &host);
+                    Some((host, api))
+                });
+            match parsed {
+                Some(api) => self.apis.push(api),
+                None => warn!("Ignoring invalid entry in {FORGE_APIS_VAR}: {entry}"),
+            }
+        }
+        self
+    }
+
+    pub fn parse(value: &str) -> Self {
+        let mut registry = Self::default();
+        let entries = value
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|entry| !entry.is_empty());
+        for entry in entries {
+            let parsed = entry
+                .split_once('=')
+                .filter(|(alias, _)| !alias.is_empty())
+                .and_then(|(alias, forge)| {
+                    Some((alias.to_ascii_lowercase(), Forge::parse(forge)?))
+                });
+            match parsed {
+                // Configured aliases take precedence over the built-in ones
+                Some(alias) => registry.aliases.insert(0, alias),
+                None => warn!("Ignoring invalid entry in {FORGES_VAR}: {entry}"),
+            }
+        }
+        registry
+    }
+
+    /// Resolves an alias or host name. Hosts without a known alias use the default layout, and
+    /// `.com` is appended to unknown names that don't contain a dot.
+    pub fn resolve(&self, domain: &str) -> Forge {
+        let mut forge = self.resolve_host(domain);
+        if let Some((_, api)) = self.apis.iter().find(|(host, _)| *host == forge.host) {
+            forge.api = Some(api.clone());
+            forge.prefer_api = true;
+        } else if let Some((host, kind)) = BUILTIN_APIS.iter().find(|(host, _)| *host == forge.host)
+        {
+            forge.api = Some(ForgeApi::for_host(*kind, host));
+        }
+        forge
+    }
+
+    fn resolve_host(&self, domain: &str) -> Forge {
+        let domain = domain.to_ascii_lowercase();
+        let find = |matches: &dyn Fn(&(String, Forge)) -> bool| {
+            self.aliases
+                .iter()
+                .find(|entry| matches(entry))
+                .map(|(_, forge)| forge.clone())
+        };
+        find(&|(alias, _)| *alias == domain)
+            // Keep the layout of known forges when they're requested by host name
+            .or_else(|| find(&|(_, forge)| forge.host == domain))
+            .unwrap_or_else(|| {
+                if domain.contains('.') {
+                    Forge::new(domain)
+                } else {
+                    Forge::new(format!("{domain}.com"))
+                }
+            })
+    }
+}
+
+/// Checks each segment of a namespace or repository name so the path can't escape the
+/// repository's location on the host. Nested namespaces like GitLab subgroups are separated by
+/// slashes.
+pub fn validate_path(path: &str) -> Result<(), ApiError> {
+    let invalid = |reason: &str| Err(ApiError::InvalidRepo(format!("{reason}: {path:?}")));
+    for segment in path.split('/') {
+        if segment.is_empty() || segment == "." || segment == ".." {
+            return invalid("Empty or relative path segment");
+        }
+        if !segment
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || ['-', '_', '.', '~'].contains(&c))
+        {
+            return invalid("Invalid character in path");
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_host_and_default_path() {
+        let forge = Forge::parse("GitLab.Example.com").unwrap();
+        assert_eq!(forge.host, "gitlab.example.com");
+        assert_eq!(forge.transport, Transport::Https);
+        assert_eq!(
+            forge.clone_url("group/sub", "repo"),
+            "https://gitlab.example.com/group/sub/repo"
+        );
+    }
+
+    #[test]
+    fn parses_custom_path() {
+        let forge = Forge::parse("git.example.org/~{user}/{repo}").unwrap();
+        assert_eq!(
+            forge.clone_url("~owner", "repo"),
+            "https://git.example.org/~owner/repo"
+        );
+    }
+
+    #[test]
+    fn parses_ssh_with_username_and_port() {
+        let forge = Forge::parse("ssh://bot@review.example.com:29418/{user}/{repo}").unwrap();
+        assert_eq!(forge.host, "review.example.com:29418");
+        assert_eq!(forge.transport, Transport::Ssh);
+        assert_eq!(
+            forge.clone_url("owner", "repo"),
+            "ssh://bot@review.example.com:29418/owner/repo"
+        );
+
+        let forge = Forge::parse("SSH://git.example.com").unwrap();
+        assert_eq!(
+            forge.clone_url("owner", "repo"),
+            "ssh://git@git.example.com/owner/repo"
+        );
+    }
+
+    #[test]
+    fn parses_git_daemon() {
+        let forge = Forge::parse("git://git.example.com").unwrap();
+        assert_eq!(forge.transport, Transport::Git);
+        assert_eq!(
+            forge.clone_url("owner", "repo"),
+            "git://git.example.com/owner/repo"
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_forges() {
+        for value in [
+            "",
+            "ftp://git.example.com",
+            "/{user}/{repo}",
+            "git.example.com/{repo}",
+            "git.example.com/{user}",
+            // Usernames are only used for SSH
+            "bot@git.example.com",
+            "https://bot@git.example.com",
+            "ssh://@git.example.com",
+        ] {
+            assert_eq!(Forge::parse(value), None, "{value} was accepted");
+        }
+    }
+
+    #[test]
+    fn configured_aliases_override_builtin_ones() {
+        let registry = ForgeRegistry::parse("github=ssh://github.com, work=git.example.com bad");
+        assert_eq!(registry.resolve("GitHub").transport, Transport::Ssh);
+        assert_eq!(registry.resolve("work").host, "git.example.com");
+        assert_eq!(registry.resolve("gitlab").host, "gitlab.com");
+        assert_eq!(registry.resolve("example").host, "example.com");
+    }
+}
@@ -0,0 +1,415 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::env;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use reqwest::blocking::{Client, RequestBuilder, Response};
+use serde_json::Value;
+use strum_macros::{EnumIter, EnumString, IntoStaticStr};
+use tokei::{Config, Language, LanguageType, Languages, Report};
+
+use crate::error::ApiError;
+use crate::params;
+
+const USER_AGENT: &str = concat!("vercel-tokei/", env!("CARGO_PKG_VERSION"));
+// Covers every page of a listing, so a slow API still leaves time to clone the repository instead
+const LIST_TIMEOUT: Duration = Duration::from_secs(10);
+const PAGE_SIZE: usize = 100;
+// Larger repositories are cheaper to clone than to page through
+const MAX_PAGES: usize = 20;
+const SYMLINK_MODE: &str = "120000";
+
+/// Where statistics come from.
+#[derive(PartialEq, Eq, Debug, Default, Clone, Copy, EnumString, EnumIter, IntoStaticStr)]
+#[strum(ascii_case_insensitive, serialize_all = "lowercase")]
+pub enum Source {
+    /// Use the forge API if the operator enabled it for the host.
+    #[default]
+    Auto,
+    /// Always clone the repository.
+    Git,
+    /// Use the forge API if the request only needs file counts.
+    Api,
+}
+
+impl Source {
+    pub fn from_query(query: &HashMap<String, Cow<str>>) -> Result<Self, &'static str> {
+        match params::SOURCE.get(query) {
+            Some(source) => Self::from_str(source)
+                .map_err(|_| "Invalid source parameter. Choices are 'auto', 'git', and 'api'"),
+            None => Ok(Self::default()),
+        }
+    }
+}
+
+/// API flavors that can list a repository's files without cloning it.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, EnumString)]
+#[strum(ascii_case_insensitive, serialize_all = "lowercase")]
+pub enum ApiKind {
+    GitHub,
+    GitLab,
+    Gitea,
+}
+
+/// A forge's REST API.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct ForgeApi {
+    kind: ApiKind,
+    base_url: String,
+    timeout: Duration,
+}
+
+impl ForgeApi {
+    pub fn new(kind: ApiKind, base_url: impl Into<String>) -> Self {
+        Self {
+            kind,
+            base_url: base_url.into().trim_end_matches('/').to_owned(),
+            timeout: LIST_TIMEOUT,
+        }
+    }
+
+    /// Uses the default API location for a host.
+    pub fn for_host(kind: ApiKind, host: &str) -> Self {
+        let base_url = match (kind, host) {
+            (ApiKind::GitHub, "github.com") => "https://api.github.com".to_owned(),
+            // GitHub Enterprise
+            (ApiKind::GitHub, host) => format!("https://{host}/api/v3"),
+            (ApiKind::GitLab, host) => format!("https://{host}/api/v4"),
+            (ApiKind::Gitea, host) => format!("https://{host}/api/v1"),
+        };
+        Self::new(kind, base_url)
+    }
+
+    /// Lists the paths of every file in the tree of commit `sha`, excluding symlinks. Gives up
+    /// once the listing takes longer than its timeout, across every page.
+    pub fn file_paths(
+        &self,
+        namespace: &str,
+        repo: &str,
+        sha: &str,
+    ) -> Result<Vec<String>, ApiError> {
+        let lister = Lister {
+            client: Client::builder()
+                .user_agent(USER_AGENT)
+                .build()
+                .map_err(ApiError::internal)?,
+            deadline: Instant::now() + self.timeout,
+        };
+        match self.kind {
+            ApiKind::GitHub => self.github_paths(&lister, namespace, repo, sha),
+            ApiKind::GitLab => self.gitlab_paths(&lister, namespace, repo, sha),
+            ApiKind::Gitea => self.gitea_paths(&lister, namespace, repo, sha),
+        }
+    }
+
+    fn github_paths(
+        &self,
+        lister: &Lister,
+        namespace: &str,
+        repo: &str,
+        sha: &str,
+    ) -> Result<Vec<String>, ApiError> {
+        let url = format!(
+            "{}/repos/{namespace}/{repo}/git/trees/{sha}?recursive=1",
+            self.base_url
+        );
+        let body = lister.get_json(
+            lister
+                .get(url)
+                .header("Accept", "application/vnd.github+json"),
+        )?;
+        if body["truncated"].as_bool().unwrap_or(false) {
+            return Err(upstream_error("file list is truncated"));
+        }
+        Ok(blob_paths(&body["tree"]))
+    }
+
+    fn gitlab_paths(
+        &self,
+        lister: &Lister,
+        namespace: &str,
+        repo: &str,
+        sha: &str,
+    ) -> Result<Vec<String>, ApiError> {
+        let project = urlencoding::encode(&format!("{namespace}/{repo}")).into_owned();
+        let mut paths = Vec::new();
+        let mut page = 1.to_string();
+        for _ in 0..MAX_PAGES {
+            let url = format!(
+                "{}/projects/{project}/repository/tree?ref={sha}&recursive=true&per_page={}&\
+                 page={page}",
+                self.base_url, PAGE_SIZE
+            );
+            let response = lister.send(lister.get(url))?;
+            let next_page = response
+                .headers()
+                .get("x-next-page")
+                .and_then(|p| p.to_str().ok())
+                .unwrap_or_default()
+                .to_owned();
+            paths.extend(blob_paths(&parse_json(response)?));
+            if next_page.is_empty() {
+                return Ok(paths);
+            }
+            page = next_page;
+        }
+        Err(upstream_error("too many files to list"))
+    }
+
+    fn gitea_paths(
+        &self,
+        lister: &Lister,
+        namespace: &str,
+        repo: &str,
+        sha: &str,
+    ) -> Result<Vec<String>, ApiError> {
+        let mut paths = Vec::new();
+        for page in 1..=MAX_PAGES {
+            let url = format!(
+                "{}/repos/{namespace}/{repo}/git/trees/{sha}?recursive=true&per_page={}&\
+                 page={page}",
+                self.base_url, PAGE_SIZE
+            );
+            let body = lister.get_json(lister.get(url))?;
+            let entries = body["tree"].as_array().map(Vec::len).unwrap_or_default();
+            paths.extend(blob_paths(&body["tree"]));
+            let total = body["total_count"].as_u64().unwrap_or_default() as usize;
+            if entries == 0 || page * PAGE_SIZE >= total {
+                return Ok(paths);
+            }
+        }
+        Err(upstream_error("too many files to list"))
+    }
+}
+
+/// Builds statistics from a list of file paths. Only file counts are known, so line counts are
+/// all zero. Languages are detected from file names alone, so files that tokei would identify by
+/// their shebang aren't counted.
+pub fn file_statistics(paths: impl IntoIterator<Item = String>, config: &Config) -> Language {
+    let include_hidden = config.hidden.unwrap_or(false);
+    let mut languages = Languages::new();
+    for path in paths {
+        let path = Path::new(&path);
+        let hidden = path
+            .components()
+            .any(|c| c.as_os_str().to_string_lossy().starts_with('.'));
+        if hidden && !include_hidden {
+            continue;
+        }
+        // tokei reads files without an extension to look for a shebang, so resolve the path
+        // somewhere that doesn't exist to keep it from reading an unrelated local file
+        let lookup_path = env::temp_dir().join("tokei-forge-api").join(path);
+        let Some(language) = LanguageType::from_path(lookup_path, config) else {
+            continue;
+        };
+        if config
+            .types
+            .as_ref()
+            .is_some_and(|types| !types.contains(&language))
+        {
+            continue;
+        }
+        languages
+            .entry(language)
+            .or_default()
+            .add_report(Report::new(path.to_owned()));
+    }
+    for language in languages.values_mut() {
+        language.total();
+    }
+    languages.total()
+}
+
+fn blob_paths(tree: &Value) -> Vec<String> {
+    tree.as_array()
+        .into_iter()
+        .flatten()
+        .filter(|entry| entry["type"] == "blob" && entry["mode"] != SYMLINK_MODE)
+        .filter_map(|entry| entry["path"].as_str().map(ToOwned::to_owned))
+        .collect()
+}
+
+/// HTTP client that shares one deadline across every request in a listing.
+struct Lister {
+    client: Client,
+    deadline: Instant,
+}
+
+impl Lister {
+    fn get(&self, url: String) -> RequestBuilder {
+        self.client.get(url)
+    }
+
+    fn send(&self, request: RequestBuilder) -> Result<Response, ApiError> {
+        let remaining = self.deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(upstream_error("timed out"));
+        }
+        request
+            .timeout(remaining)
+            .send()
+            .and_then(Response::error_for_status)
+            .map_err(|e| upstream_error(&e.to_string()))
+    }
+
+    fn get_json(&self, request: RequestBuilder) -> Result<Value, ApiError> {
+        parse_json(self.send(request)?)
+    }
+}
+
+fn parse_json(response: Response) -> Result<Value, ApiError> {
+    let text = response
+        .text()
+        .map_err(|e| upstream_error(&e.to_string()))?;
+    serde_json::from_str(&text).map_err(|e| upstream_error(&e.to_string()))
+}
+
+fn upstream_error(message: &str) -> ApiError {
+    ApiError::UpstreamUnavailable(format!("Error listing files from forge API: {message}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    use serde_json::json;
+
+    use super::*;
+
+    /// Serves `route(path)` on a random local port until the test exits. Returns the base URL.
+    fn serve(
+        route: impl Fn(&str) -> Option<(Vec<(&'static str, String)>, Value)> + Send + 'static,
+    ) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                let mut line = String::new();
+                while reader.read_line(&mut line).unwrap() > 2 {
+                    line.clear();
+                }
+                let path = request_line.split(' ').nth(1).unwrap_or_default();
+                let (status, headers, body) = match route(path) {
+                    Some((headers, body)) => ("200 OK", headers, body.to_string()),
+                    None => ("404 Not Found", Vec::new(), String::new()),
+                };
+                let mut response = format!(
+                    "HTTP/1.1 {status}\r\nContent-Type: application/json\r\nContent-Length: \
+                     {}\r\nConnection: close\r\n",
+                    body.len()
+                );
+                for (name, value) in headers {
+                    response += &format!("{name}: {value}\r\n");
+                }
+                response += "\r\n";
+                response += &body;
+                let _ = stream.write_all(response.as_bytes());
+            }
+        });
+        base_url
+    }
+
+    fn blob(path: &str) -> Value {
+        json!({ "path": path, "type": "blob", "mode": "100644" })
+    }
+
+    #[test]
+    fn github_lists_tree() {
+        let base_url = serve(|path| {
+            (path == "/repos/owner/repo/git/trees/abc?recursive=1").then(|| {
+                let tree = json!([blob("src/main.rs"), { "path": "src", "type": "tree" }]);
+                (Vec::new(), json!({ "truncated": false, "tree": tree }))
+            })
+        });
+        let api = ForgeApi::new(ApiKind::GitHub, base_url);
+        assert_eq!(
+            api.file_paths("owner", "repo", "abc").unwrap(),
+            ["src/main.rs"]
+        );
+    }
+
+    #[test]
+    fn github_truncated_tree_is_an_error() {
+        let base_url = serve(|_| {
+            Some((
+                Vec::new(),
+                json!({ "truncated": true, "tree": [blob("a.rs")] }),
+            ))
+        });
+        let api = ForgeApi::new(ApiKind::GitHub, base_url);
+        // The handler falls back to cloning on any error
+        assert!(matches!(
+            api.file_paths("owner", "repo", "abc"),
+            Err(ApiError::UpstreamUnavailable(_))
+        ));
+    }
+
+    #[test]
+    fn gitlab_follows_next_page() {
+        let base_url = serve(|path| {
+            let prefix = "/projects/group%2Fsubgroup%2Frepo/repository/tree?ref=abc&\
+                          recursive=true&per_page=100&page=";
+            match path.strip_prefix(prefix)? {
+                "1" => Some((vec![("x-next-page", "2".to_owned())], json!([blob("a.rs")]))),
+                "2" => Some((vec![("x-next-page", String::new())], json!([blob("b.rs")]))),
+                _ => None,
+            }
+        });
+        let api = ForgeApi::new(ApiKind::GitLab, base_url);
+        assert_eq!(
+            api.file_paths("group/subgroup", "repo", "abc").unwrap(),
+            ["a.rs", "b.rs"]
+        );
+    }
+
+    #[test]
+    fn gitea_pages_until_total_count() {
+        let base_url = serve(|path| {
+            let prefix = "/repos/owner/repo/git/trees/abc?recursive=true&per_page=100&page=";
+            let page: usize = path.strip_prefix(prefix)?.parse().ok()?;
+            // One file per page shows which pages were requested
+            let body = json!({ "tree": [blob(&format!("{page}.rs"))], "total_count": 150 });
+            Some((Vec::new(), body))
+        });
+        let api = ForgeApi::new(ApiKind::Gitea, base_url);
+        assert_eq!(
+            api.file_paths("owner", "repo", "abc").unwrap(),
+            ["1.rs", "2.rs"]
+        );
+    }
+
+    #[test]
+    fn listing_stops_at_deadline() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let api = ForgeApi {
+            timeout: Duration::from_millis(200),
+            ..ForgeApi::new(
+                ApiKind::GitHub,
+                format!("http://{}", listener.local_addr().unwrap()),
+            )
+        };
+        let started = Instant::now();
+        // The listener accepts connections but never responds
+        assert!(api.file_paths("owner", "repo", "abc").is_err());
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn blob_paths_skips_symlinks_and_trees() {
+        let tree = json!([
+            blob("src/lib.rs"),
+            { "path": "link.rs", "type": "blob", "mode": SYMLINK_MODE },
+            { "path": "src", "type": "tree", "mode": "040000" },
+            { "path": "vendor", "type": "commit", "mode": "160000" },
+        ]);
+        assert_eq!(blob_paths(&tree), ["src/lib.rs"]);
+    }
+}
@@ -0,0 +1,302 @@
+use std::env;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, ToSocketAddrs};
+
+use crate::error::ApiError;
+
+const ALLOWED_HOSTS_VAR: &str = "TOKEI_ALLOWED_HOSTS";
+const DENIED_HOSTS_VAR: &str = "TOKEI_DENIED_HOSTS";
+// Only used for DNS resolution, the port doesn't need to match the one used by git
+const RESOLVE_PORT: u16 = 443;
+
+/// Decides which hosts the service is allowed to connect to. Since the host comes straight from
+/// the request, this keeps the service from being used to reach internal infrastructure.
+#[derive(Debug, Default)]
+pub struct HostPolicy {
+    allowed: Vec<String>,
+    denied: Vec<String>,
+}
+
+impl HostPolicy {
+    /// Reads comma-separated host patterns from `TOKEI_ALLOWED_HOSTS` and `TOKEI_DENIED_HOSTS`.
+    /// Patterns can start with `*.` to match any subdomain. If no allowed hosts are configured,
+    /// every public host that isn't denied is allowed.
+    pub fn from_env() -> Self {
+        Self::new(
+            &env::var(ALLOWED_HOSTS_VAR).unwrap_or_default(),
+            &env::var(DENIED_HOSTS_VAR).unwrap_or_default(),
+        )
+    }
+
+    pub fn new(allowed: &str, denied: &str) -> Self {
+        Self {
+            allowed: parse_patterns(allowed),
+            denied: parse_patterns(denied),
+        }
+    }
+
+    /// Checks a host, optionally followed by a port.
+    pub fn check(&self, host: &str) -> Result<(), ApiError> {
+        let host = host.to_ascii_lowercase();
+        let forbidden = |reason: &str| Err(ApiError::Forbidden(format!("Host {host} {reason}")));
+
+        // Check before splitting off the port since a bare IPv6 address contains colons
+        if host.trim_matches(['[', ']']).parse::<IpAddr>().is_ok() {
+            return forbidden("is an IP address. Please use a hostname instead.");
+        }
+        let host = match host.rsplit_once(':') {
+            Some((host, port)) if port.parse::<u16>().is_ok() => host,
+            _ => &host,
+        }
+        .trim_end_matches('.')
+        .to_owned();
+        if host.trim_matches(['[', ']']).parse::<IpAddr>().is_ok() {
+            return forbidden("is an IP address. Please use a hostname instead.");
+        }
+        // Anything else (ex: userinfo like "user@host") could change which host git connects to
+        if host.is_empty()
+            || !host
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.')
+        {
+            return Err(ApiError::InvalidRepo(format!("Invalid host: {host}")));
+        }
+        if host == "localhost" || host.ends_with(".localhost") {
+            return forbidden("is not allowed");
+        }
+        if self.denied.iter().any(|p| matches_pattern(p, &host)) {
+            return forbidden("is not allowed");
+        }
+        if !self.allowed.is_empty() && !self.allowed.iter().any(|p| matches_pattern(p, &host)) {
+            return forbidden("is not in the list of allowed hosts");
+        }
+        // Hosts listed exactly are trusted by the operator, so they may point to internal
+        // addresses. Wildcards could match attacker-controlled subdomains, so they aren't.
+        if self.allowed.contains(&host) {
+            return Ok(());
+        }
+
+        let addresses = (host.as_str(), RESOLVE_PORT)
+            .to_socket_addrs()
+            .map_err(|e| {
+                ApiError::UpstreamUnavailable(format!("Error resolving host {host}: {e}"))
+            })?;
+        for address in addresses {
+            if !is_public(address.ip()) {
+                return forbidden("resolves to a private address");
+            }
+        }
+        Ok(())
+    }
+}
+
+fn parse_patterns(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(|p| p.trim().trim_end_matches('.').to_ascii_lowercase())
+        .filter(|p| !p.is_empty())
+        .collect()
+}
+
+fn matches_pattern(pattern: &str, host: &str) -> bool {
+    match pattern.strip_prefix("*.") {
+        Some(suffix) => host
+            .strip_suffix(suffix)
+            .is_some_and(|subdomain| subdomain.ends_with('.')),
+        None => pattern == host,
+    }
+}
+
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_v4(ip),
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public_v4(ip),
+            None => is_public_v6(ip),
+        },
+    }
+}
+
+fn is_public_v4(ip: Ipv4Addr) -> bool {
+    let [first, second, third, _] = ip.octets();
+    // 100.64.0.0/10 is reserved for carrier-grade NAT
+    let is_shared = first == 100 && (64..128).contains(&second);
+    // 192.0.0.0/24 is reserved for protocol assignments
+    let is_protocol = first == 192 && second == 0 && third == 0;
+    // 198.18.0.0/15 is reserved for benchmarking
+    let is_benchmarking = first == 198 && (second == 18 || second == 19);
+    // 240.0.0.0/4 is reserved for future use, and includes the broadcast address
+    let is_reserved = first >= 240;
+    !(ip.is_private()
+        || ip.is_loopback()
+        || ip.is_link_local()
+        || ip.is_unspecified()
+        || ip.is_documentation()
+        || ip.is_multicast()
+        || is_shared
+        || is_protocol
+        || is_benchmarking
+        || is_reserved
+        || first == 0)
+}
+
+fn is_public_v6(ip: Ipv6Addr) -> bool {
+    let segments = ip.segments();
+    // NAT64 (64:ff9b::/96) and 6to4 (2002::/16) addresses lead to the IPv4 address they embed
+    if segments[..6] == [0x64, 0xff9b, 0, 0, 0, 0] {
+        return is_public_v4(Ipv4Addr::from_bits(ip.to_bits() as u32));
+    }
+    if segments[0] == 0x2002 {
+        return is_public_v4(Ipv4Addr::from_bits((ip.to_bits() >> 80) as u32));
+    }
+    !(ip.is_loopback()
+        || ip.is_unspecified()
+        || ip.is_multicast()
+        || ip.is_unique_local()
+        || ip.is_unicast_link_local())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn is_forbidden(result: Result<(), ApiError>) -> bool {
+        matches!(result, Err(ApiError::Forbidden(_)))
+    }
+
+    #[test]
+    fn rejects_ip_literals() {
+        let policy = HostPolicy::default();
+        for host in [
+            "8.8.8.8",
+            "8.8.8.8:443",
+            "127.0.0.1",
+            "127.0.0.1:8080",
+            "::1",
+            "[::1]",
+            "[::1]:443",
+            "[2606:4700::1111]",
+            "2606:4700::1111",
+        ] {
+            assert!(is_forbidden(policy.check(host)), "{host} was allowed");
+        }
+    }
+
+    #[test]
+    fn rejects_localhost() {
+        let policy = HostPolicy::new("localhost,*.localhost", "");
+        for host in ["localhost", "LOCALHOST:8080", "localhost.", "git.localhost"] {
+            assert!(is_forbidden(policy.check(host)), "{host} was allowed");
+        }
+    }
+
+    #[test]
+    fn rejects_userinfo() {
+        let policy = HostPolicy::new("example.com", "");
+        assert!(matches!(
+            policy.check("user@example.com"),
+            Err(ApiError::InvalidRepo(_))
+        ));
+    }
+
+    #[test]
+    fn wildcard_does_not_match_bare_domain() {
+        assert!(matches_pattern("*.example.com", "git.example.com"));
+        assert!(matches_pattern("*.example.com", "a.b.example.com"));
+        assert!(!matches_pattern("*.example.com", "example.com"));
+        assert!(!matches_pattern("*.example.com", "badexample.com"));
+
+        let policy = HostPolicy::new("*.example.com", "");
+        match policy.check("example.com") {
+            Err(ApiError::Forbidden(message)) => {
+                assert!(message.ends_with("is not in the list of allowed hosts"))
+            }
+            result => panic!("expected example.com to be rejected, got {result:?}"),
+        }
+    }
+
+    #[test]
+    fn deny_wins_over_allow() {
+        let policy = HostPolicy::new("git.example.com,*.example.com", "git.example.com");
+        assert!(is_forbidden(policy.check("git.example.com")));
+        let policy = HostPolicy::new("git.example.com", "*.example.com");
+        assert!(is_forbidden(policy.check("git.example.com:443")));
+    }
+
+    #[test]
+    fn exact_allow_skips_resolution() {
+        // The .invalid TLD never resolves, so this only passes if resolution is skipped
+        let policy = HostPolicy::new("git.internal.invalid", "");
+        policy.check("git.internal.invalid").unwrap();
+        policy.check("GIT.internal.invalid.:8443").unwrap();
+
+        let policy = HostPolicy::new("*.internal.invalid", "");
+        assert!(matches!(
+            policy.check("git.internal.invalid"),
+            Err(ApiError::UpstreamUnavailable(_))
+        ));
+    }
+
+    #[test]
+    fn classifies_ipv4_ranges() {
+        for ip in [
+            "0.1.2.3",
+            "10.0.0.1",
+            "100.64.0.1",
+            "100.127.255.254",
+            "127.0.0.1",
+            "169.254.169.254",
+            "172.16.0.1",
+            "192.0.0.8",
+            "192.0.2.1",
+            "192.168.1.1",
+            "198.18.0.1",
+            "198.19.255.255",
+            "198.51.100.1",
+            "203.0.113.1",
+            "224.0.0.1",
+            "240.0.0.1",
+            "255.255.255.255",
+        ] {
+            assert!(!is_public(ip.parse().unwrap()), "{ip} is public");
+        }
+        for ip in [
+            "1.1.1.1",
+            "8.8.8.8",
+            "100.128.0.1",
+            "172.32.0.1",
+            "192.0.1.1",
+            "198.20.0.1",
+            "223.255.255.255",
+        ] {
+            assert!(is_public(ip.parse().unwrap()), "{ip} isn't public");
+        }
+    }
+
+    #[test]
+    fn classifies_ipv6_ranges() {
+        for ip in [
+            "::",
+            "::1",
+            "fc00::1",
+            "fd12:3456::1",
+            "fe80::1",
+            "ff02::1",
+            "::ffff:127.0.0.1",
+            "::ffff:10.0.0.1",
+            "64:ff9b::7f00:1",
+            "64:ff9b::a9fe:a9fe",
+            "2002:7f00:1::",
+            "2002:c0a8:101::1",
+        ] {
+            assert!(!is_public(ip.parse().unwrap()), "{ip} is public");
+        }
+        for ip in [
+            "2606:4700::1111",
+            "::ffff:8.8.8.8",
+            "64:ff9b::808:808",
+            "2002:808:808::1",
+        ] {
+            assert!(is_public(ip.parse().unwrap()), "{ip} isn't public");
+        }
+    }
+}
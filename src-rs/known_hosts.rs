@@ -0,0 +1,204 @@
+use std::{env, fs};
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use git2::cert::Cert;
+use git2::{CertificateCheckStatus, ErrorClass, ErrorCode, RemoteCallbacks};
+use sha2::{Digest, Sha256};
+use tracing::warn;
+
+const KNOWN_HOSTS_VAR: &str = "TOKEI_SSH_KNOWN_HOSTS";
+const DEFAULT_PORT_SUFFIX: &str = ":22";
+
+/// SSH host keys configured by the operator. Serverless platforms don't come with a
+/// `known_hosts` file, so without these libgit2 rejects every SSH remote.
+#[derive(Debug, Default)]
+pub struct KnownHosts {
+    entries: Vec<KnownHost>,
+}
+
+#[derive(Debug)]
+struct KnownHost {
+    /// Host name, followed by the port if it isn't the default.
+    host: String,
+    key: Vec<u8>,
+}
+
+impl KnownHosts {
+    /// Reads `TOKEI_SSH_KNOWN_HOSTS`, which can contain either lines in the OpenSSH
+    /// `known_hosts` format or a path to such a file.
+    pub fn from_env() -> Self {
+        let Ok(value) = env::var(KNOWN_HOSTS_VAR).map(|v| v.trim().to_owned()) else {
+            return Self::default();
+        };
+        if value.is_empty() || value.contains(char::is_whitespace) {
+            return Self::parse(&value);
+        }
+        match fs::read_to_string(&value) {
+            Ok(contents) => Self::parse(&contents),
+            Err(e) => {
+                warn!("Error reading {KNOWN_HOSTS_VAR} from {value}: {e}");
+                Self::default()
+            }
+        }
+    }
+
+    /// Parses `host[,host...] key-type base64-key` lines. Hosts on a non-default port are written
+    /// as `[host]:port`. Hashed hosts, wildcards, and markers like `@revoked` aren't supported.
+    pub fn parse(value: &str) -> Self {
+        let mut entries = Vec::new();
+        for line in value.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut fields = line.split_whitespace();
+            let (Some(hosts), Some(_key_type), Some(key)) =
+                (fields.next(), fields.next(), fields.next())
+            else {
+                warn!("Ignoring invalid line in {KNOWN_HOSTS_VAR}: {line}");
+                continue;
+            };
+            if hosts.starts_with(['@', '|']) || hosts.contains(['*', '?', '!']) {
+                warn!("Ignoring unsupported line in {KNOWN_HOSTS_VAR}: {line}");
+                continue;
+            }
+            let Ok(key) = STANDARD.decode(key) else {
+                warn!("Ignoring line with an invalid key in {KNOWN_HOSTS_VAR}: {line}");
+                continue;
+            };
+            for host in hosts.split(',').filter(|h| !h.is_empty()) {
+                let host = match host.strip_prefix('[').and_then(|h| h.split_once("]:")) {
+                    Some((host, port)) => format!("{host}:{port}"),
+                    None => host.to_owned(),
+                };
+                entries.push(KnownHost {
+                    host: normalize_host(&host),
+                    key: key.clone(),
+                });
+            }
+        }
+        Self { entries }
+    }
+
+    /// Adds a callback that checks SSH host keys against the configured ones. `host` is the
+    /// host being connected to, optionally followed by a port. Hosts without configured keys are
+    /// left to libgit2's own checks.
+    pub fn verify<'a>(&'a self, host: &'a str, callbacks: &mut RemoteCallbacks<'a>) {
+        callbacks.certificate_check(move |cert, _| self.check(host, cert));
+    }
+
+    fn check(&self, host: &str, cert: &Cert<'_>) -> Result<CertificateCheckStatus, git2::Error> {
+        let Some(hostkey) = cert.as_hostkey() else {
+            return Ok(CertificateCheckStatus::CertificatePassthrough);
+        };
+        match self.matches(host, hostkey.hostkey(), hostkey.hash_sha256()) {
+            Some(true) => Ok(CertificateCheckStatus::CertificateOk),
+            Some(false) => Err(git2::Error::new(
+                ErrorCode::Certificate,
+                ErrorClass::Ssh,
+                format!("host key for {host} doesn't match {KNOWN_HOSTS_VAR}"),
+            )),
+            None => Ok(CertificateCheckStatus::CertificatePassthrough),
+        }
+    }
+
+    /// Whether the server's key matches one configured for the host, or `None` if the host has
+    /// no configured keys. Older versions of libssh2 only provide the key's hash.
+    fn matches(&self, host: &str, key: Option<&[u8]>, sha256: Option<&[u8; 32]>) -> Option<bool> {
+        let host = normalize_host(host);
+        let mut known = self.entries.iter().filter(|e| e.host == host).peekable();
+        known.peek()?;
+        Some(known.any(|entry| match (key, sha256) {
+            (Some(key), _) => entry.key == key,
+            (None, Some(sha256)) => Sha256::digest(&entry.key)[..] == sha256[..],
+            (None, None) => false,
+        }))
+    }
+}
+
+fn normalize_host(host: &str) -> String {
+    let host = host.to_ascii_lowercase();
+    match host.strip_suffix(DEFAULT_PORT_SUFFIX) {
+        Some(host) => host.to_owned(),
+        None => host,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &str = "AAAAC3NzaC1lZDI1NTE5AAAAIOMqqnkVzrm0SdG6UOoqKLsabgH5C9okWi0dh2l9GKJl";
+    const OTHER_KEY: &str = "AAAAC3NzaC1lZDI1NTE5AAAAIHyKqsYqBw4wWkS2H1fBqpcnBY2rGvjTEJvBEB3ZuA6J";
+
+    fn key(value: &str) -> Vec<u8> {
+        STANDARD.decode(value).unwrap()
+    }
+
+    #[test]
+    fn matches_configured_key() {
+        let known_hosts = KnownHosts::parse(&format!(
+            "# comment\n\ngithub.com,GitLab.com ssh-ed25519 {KEY} comment\n"
+        ));
+        let key = key(KEY);
+        assert_eq!(
+            known_hosts.matches("github.com", Some(&key), None),
+            Some(true)
+        );
+        assert_eq!(
+            known_hosts.matches("gitlab.com:22", Some(&key), None),
+            Some(true)
+        );
+        assert_eq!(
+            known_hosts.matches("github.com", Some(&self::key(OTHER_KEY)), None),
+            Some(false)
+        );
+        assert_eq!(known_hosts.matches("codeberg.org", Some(&key), None), None);
+    }
+
+    #[test]
+    fn matches_hash_without_key() {
+        let known_hosts = KnownHosts::parse(&format!("github.com ssh-ed25519 {KEY}"));
+        let sha256: [u8; 32] = Sha256::digest(key(KEY)).into();
+        let other: [u8; 32] = Sha256::digest(key(OTHER_KEY)).into();
+        assert_eq!(
+            known_hosts.matches("github.com", None, Some(&sha256)),
+            Some(true)
+        );
+        assert_eq!(
+            known_hosts.matches("github.com", None, Some(&other)),
+            Some(false)
+        );
+        assert_eq!(known_hosts.matches("github.com", None, None), Some(false));
+    }
+
+    #[test]
+    fn matches_port() {
+        let known_hosts = KnownHosts::parse(&format!(
+            "[review.example.com]:29418 ssh-ed25519 {KEY}\n[git.example.com]:22 ssh-ed25519 {KEY}"
+        ));
+        let key = key(KEY);
+        assert_eq!(
+            known_hosts.matches("review.example.com:29418", Some(&key), None),
+            Some(true)
+        );
+        assert_eq!(
+            known_hosts.matches("review.example.com", Some(&key), None),
+            None
+        );
+        assert_eq!(
+            known_hosts.matches("git.example.com", Some(&key), None),
+            Some(true)
+        );
+    }
+
+    #[test]
+    fn skips_unsupported_lines() {
+        let known_hosts = KnownHosts::parse(&format!(
+            "@cert-authority *.example.com ssh-ed25519 {KEY}\n|1|aGFzaA==|aGFzaA== ssh-ed25519 \
+             {KEY}\n*.example.com ssh-ed25519 {KEY}\ngit.example.com ssh-ed25519 \
+             not-base64!\ngit.example.com ssh-ed25519\n"
+        ));
+        assert!(known_hosts.entries.is_empty());
+    }
+}
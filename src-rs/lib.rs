@@ -1,7 +1,21 @@
+pub mod cache;
 pub mod category;
 pub mod color;
 pub mod content_type;
+pub mod credentials;
+pub mod error;
+pub mod fetch;
+pub mod forge;
+pub mod forge_api;
+pub mod host_policy;
+pub mod known_hosts;
+pub mod limits;
+pub mod openapi;
+pub mod params;
+pub mod render;
 pub mod settings;
+pub mod signing;
+pub mod stats;
 pub mod style;
 pub mod theme;
 pub mod util;
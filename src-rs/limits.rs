@@ -0,0 +1,141 @@
+use std::cell::Cell;
+use std::env;
+use std::ffi::c_int;
+use std::time::{Duration, Instant};
+
+use git2::{ErrorCode, Progress, RemoteCallbacks};
+use tracing::warn;
+
+use crate::error::ApiError;
+
+const MAX_REPO_BYTES_VAR: &str = "TOKEI_MAX_REPO_BYTES";
+const CLONE_TIMEOUT_VAR: &str = "TOKEI_CLONE_TIMEOUT_SECONDS";
+// Leaves room in Vercel's 512 MB /tmp for the checked out files
+pub const DEFAULT_MAX_REPO_BYTES: usize = 256 * 1024 * 1024;
+pub const DEFAULT_CLONE_TIMEOUT: Duration = Duration::from_secs(45);
+
+/// Caps how much data a clone can download and how long it can take. A limit of `None` disables
+/// the check.
+#[derive(Clone, Copy, Debug)]
+pub struct CloneLimits {
+    pub max_bytes: Option<usize>,
+    pub timeout: Option<Duration>,
+}
+
+impl Default for CloneLimits {
+    fn default() -> Self {
+        Self {
+            max_bytes: Some(DEFAULT_MAX_REPO_BYTES),
+            timeout: Some(DEFAULT_CLONE_TIMEOUT),
+        }
+    }
+}
+
+impl CloneLimits {
+    /// Reads `TOKEI_MAX_REPO_BYTES` and `TOKEI_CLONE_TIMEOUT_SECONDS`. Setting either one to `0`
+    /// disables it.
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        Self {
+            max_bytes: read_limit(MAX_REPO_BYTES_VAR, defaults.max_bytes),
+            timeout: read_limit(CLONE_TIMEOUT_VAR, defaults.timeout.map(|t| t.as_secs()))
+                .map(Duration::from_secs),
+        }
+    }
+
+    /// Applies the timeout to libgit2's sockets so servers that stall while connecting, listing
+    /// refs, or mid-transfer are cut off too, since the progress callbacks only run when data
+    /// arrives. libgit2's options are global, so call this once before any git operations.
+    pub fn apply_server_timeouts(&self) {
+        let Some(timeout) = self.timeout else {
+            return;
+        };
+        let millis = c_int::try_from(timeout.as_millis()).unwrap_or(c_int::MAX);
+        // SAFETY: only called at startup, before any other thread uses libgit2
+        let result = unsafe {
+            git2::opts::set_server_connect_timeout_in_milliseconds(millis)
+                .and_then(|()| git2::opts::set_server_timeout_in_milliseconds(millis))
+        };
+        if let Err(e) = result {
+            warn!("Error setting git server timeouts: {e}");
+        }
+    }
+
+    /// Maps a git error caused by the server timeouts to the matching API error.
+    pub fn timeout_error(&self, err: &git2::Error) -> Option<ApiError> {
+        match (err.code(), self.timeout) {
+            (ErrorCode::Timeout, Some(limit)) => Some(ApiError::Timeout { limit }),
+            _ => None,
+        }
+    }
+
+    /// Starts tracking a single clone.
+    pub fn start(&self) -> CloneGuard {
+        CloneGuard {
+            limits: *self,
+            started: Instant::now(),
+            exceeded: Cell::new(None),
+        }
+    }
+}
+
+/// Tracks the progress of a clone against its limits.
+pub struct CloneGuard {
+    limits: CloneLimits,
+    started: Instant,
+    exceeded: Cell<Option<ApiError>>,
+}
+
+impl CloneGuard {
+    /// Adds callbacks that abort the transfer once a limit is exceeded.
+    pub fn watch<'a>(&'a self, callbacks: &mut RemoteCallbacks<'a>) {
+        callbacks.transfer_progress(|progress| self.check(&progress));
+        callbacks.sideband_progress(|_| self.check_time());
+    }
+
+    /// The limit that caused the transfer to be aborted, if any.
+    pub fn take_error(&self) -> Option<ApiError> {
+        self.exceeded.take()
+    }
+
+    fn check(&self, progress: &Progress<'_>) -> bool {
+        if let Some(limit_bytes) = self.limits.max_bytes
+            && progress.received_bytes() > limit_bytes
+        {
+            return self.abort(ApiError::RepoTooLarge { limit_bytes });
+        }
+        self.check_time()
+    }
+
+    fn check_time(&self) -> bool {
+        match self.limits.timeout {
+            Some(limit) if self.started.elapsed() > limit => {
+                self.abort(ApiError::Timeout { limit })
+            }
+            _ => true,
+        }
+    }
+
+    fn abort(&self, error: ApiError) -> bool {
+        self.exceeded.set(Some(error));
+        // Returning false tells libgit2 to cancel the transfer
+        false
+    }
+}
+
+fn read_limit<T: std::str::FromStr + Default + PartialEq>(
+    name: &str,
+    default: Option<T>,
+) -> Option<T> {
+    let Ok(value) = env::var(name) else {
+        return default;
+    };
+    match value.trim().parse::<T>() {
+        Ok(limit) if limit == T::default() => None,
+        Ok(limit) => Some(limit),
+        Err(_) => {
+            warn!("Ignoring invalid value for {name}: {value}");
+            default
+        }
+    }
+}
@@ -0,0 +1,200 @@
+use serde_json::{Value, json};
+use strum::IntoEnumIterator;
+
+use crate::content_type::ContentType;
+use crate::params::{self, Param};
+
+/// OpenAPI 3.1 description of the badge API. Query parameters come from the same definitions that
+/// `Settings::from_query` reads them through.
+pub fn document() -> Value {
+    json!({
+        "openapi": "3.1.0",
+        "info": {
+            "title": "Tokei Serverless API",
+            "description": "Count lines of code in a git repository and render the result as a \
+                            badge or report.",
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "paths": {
+            "/tokei/{domain}/{user}/{repo}": {
+                "get": {
+                    "summary": "Count lines of code in a repository",
+                    "operationId": "getRepoStatistics",
+                    "parameters": parameters(),
+                    "responses": {
+                        "200": {
+                            "description": "Statistics in the requested format",
+                            "content": success_content(),
+                        },
+                        "400": error_response("The query parameters or repository are invalid"),
+                        "401": error_response("The repository requires authentication"),
+                        "403": error_response("Access to the repository was denied"),
+                        "404": error_response("The repository or branch doesn't exist"),
+                        "406": error_response(
+                            "None of the media types in the Accept header are supported"
+                        ),
+                        "413": error_response("The repository is too large to download"),
+                        "502": error_response(
+                            "The repository host is unreachable or the clone failed"
+                        ),
+                        "503": error_response(
+                            "The server is temporarily unable to download repositories. The \
+                             Retry-After header says when to try again."
+                        ),
+                        "504": error_response("The repository took too long to download"),
+                    },
+                },
+            },
+            "/tokei": {
+                "get": {
+                    "summary": "Interactive badge builder and documentation",
+                    "operationId": "getIndex",
+                    "responses": {
+                        "200": {
+                            "description": "Badge builder page",
+                            "content": { "text/html": {} },
+                        },
+                        "304": { "description": "The page has not been modified" },
+                    },
+                },
+            },
+            "/tokei/openapi.json": {
+                "get": {
+                    "summary": "This document",
+                    "operationId": "getOpenApi",
+                    "responses": {
+                        "200": {
+                            "description": "OpenAPI document",
+                            "content": { "application/json": {} },
+                        },
+                    },
+                },
+            },
+        },
+    })
+}
+
+fn parameters() -> Vec<Value> {
+    let path = [
+        path_parameter(
+            "domain",
+            "Git host or forge alias (github, gitlab, bitbucket, codeberg, sourcehut, gitea). \
+             `.com` is appended to other values that don't contain a dot.",
+        ),
+        path_parameter(
+            "user",
+            "Owner of the repository. Nested namespaces like GitLab subgroups can be included as \
+             additional path segments before the repository name.",
+        ),
+        path_parameter("repo", "Name of the repository"),
+    ];
+    path.into_iter()
+        .chain(params::ALL.iter().map(|param| query_parameter(param)))
+        .collect()
+}
+
+fn success_content() -> Value {
+    let mut content = serde_json::Map::new();
+    for content_type in ContentType::iter() {
+        let media_type = content_type
+            .response_type()
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .to_owned();
+        content.entry(media_type).or_insert_with(|| json!({}));
+    }
+    Value::Object(content)
+}
+
+/// Errors are rendered as a badge for image formats, JSON for JSON formats, and plain text
+/// otherwise.
+fn error_response(description: &str) -> Value {
+    json!({
+        "description": description,
+        "content": {
+            "application/json": {
+                "schema": {
+                    "type": "object",
+                    "properties": {
+                        "error": { "type": "string" },
+                        "message": { "type": "string" },
+                        "status": { "type": "integer" },
+                    },
+                    "required": ["error", "message", "status"],
+                },
+            },
+            "image/svg+xml": {},
+            "image/png": {},
+            "text/plain": { "schema": { "type": "string" } },
+        },
+    })
+}
+
+fn path_parameter(name: &str, description: &str) -> Value {
+    json!({
+        "name": name,
+        "in": "path",
+        "required": true,
+        "description": description,
+        "schema": { "type": "string" },
+    })
+}
+
+fn query_parameter(param: &Param) -> Value {
+    json!({
+        "name": param.name,
+        "in": "query",
+        "required": false,
+        "description": param.description,
+        "schema": param.schema(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::settings::Settings;
+
+    #[test]
+    fn documents_every_parameter_once() {
+        let names: Vec<_> = parameters()
+            .iter()
+            .map(|p| p["name"].as_str().unwrap().to_ascii_lowercase())
+            .collect();
+        for (i, name) in names.iter().enumerate() {
+            assert!(!names[i + 1..].contains(name), "{name} is documented twice");
+        }
+    }
+
+    #[test]
+    fn documented_values_are_accepted() {
+        for param in params::ALL {
+            let schema = param.schema();
+            let values = schema["enum"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .chain(schema.get("default"));
+            for value in values {
+                let value = match value {
+                    Value::String(value) => value.clone(),
+                    value => value.to_string(),
+                };
+                // Keys reach the parsers lowercased, like in the handler
+                let query = HashMap::from([(
+                    param.name.to_ascii_lowercase(),
+                    Cow::Borrowed(value.as_str()),
+                )]);
+                assert!(
+                    Settings::from_query(&query).is_ok(),
+                    "{}={value} is rejected",
+                    param.name
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,220 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+use serde_json::{Value, json};
+use strum::IntoEnumIterator;
+
+use crate::category::Category;
+use crate::color::Color;
+use crate::content_type::ContentType;
+use crate::forge_api::Source;
+use crate::settings::{DEFAULT_CACHE_SECONDS, DEFAULT_FILE_LIMIT, MAX_FILE_LIMIT};
+use crate::style::Style;
+use crate::theme::{DEFAULT_COLOR, DEFAULT_LABEL_COLOR};
+
+/// A query parameter of the badge route. The parsers read parameters through these definitions
+/// and the OpenAPI document is generated from them, so the two can't drift apart.
+pub struct Param {
+    /// Name as documented. Requests are matched case-insensitively.
+    pub name: &'static str,
+    pub description: &'static str,
+    schema: fn() -> Value,
+}
+
+impl Param {
+    /// Reads the parameter from a query whose keys have been lowercased.
+    pub fn get<'a, 'q>(
+        &self,
+        query: &'a HashMap<String, Cow<'q, str>>,
+    ) -> Option<&'a Cow<'q, str>> {
+        query.get(&self.name.to_ascii_lowercase())
+    }
+
+    /// JSON schema of the parameter's value, including its default.
+    pub fn schema(&self) -> Value {
+        (self.schema)()
+    }
+}
+
+pub const NAMESPACE: Param = Param {
+    name: "namespace",
+    description: "Full slash-separated namespace of the repository. Replaces `user` when set.",
+    schema: || json!({ "type": "string", "examples": ["group/subgroup"] }),
+};
+
+pub const CATEGORY: Param = Param {
+    name: "category",
+    description: "Which metric is displayed",
+    schema: || enum_schema(Category::iter(), Category::default()),
+};
+
+pub const FORMAT: Param = Param {
+    name: "format",
+    description: "Output format. If omitted, the format is negotiated from the Accept header.",
+    schema: || enum_schema(ContentType::iter(), ContentType::default()),
+};
+
+pub const STYLE: Param = Param {
+    name: "style",
+    description: "SVG badge style",
+    schema: || enum_schema(Style::iter(), Style::default()),
+};
+
+pub const LABEL_COLOR: Param = Param {
+    name: "labelColor",
+    description: "Background color of the label. Accepts a named color or any CSS color.",
+    schema: || color_schema(DEFAULT_LABEL_COLOR),
+};
+
+pub const COLOR: Param = Param {
+    name: "color",
+    description: "Background color of the metric. Accepts a named color or any CSS color.",
+    schema: || color_schema(DEFAULT_COLOR),
+};
+
+pub const LABEL: Param = Param {
+    name: "label",
+    description: "Override the default label text. Pass an empty value to disable.",
+    schema: || json!({ "type": "string" }),
+};
+
+pub const LOGO: Param = Param {
+    name: "logo",
+    description: "HTTP URL or data URL of an SVG logo shown before the label",
+    schema: || json!({ "type": "string" }),
+};
+
+pub const LOGO_AS_LABEL: Param = Param {
+    name: "logoAsLabel",
+    description: "Use the label background color for the logo when the label is empty",
+    schema: bool_schema,
+};
+
+pub const RAW: Param = Param {
+    name: "raw",
+    description: "Show the exact count instead of abbreviating it",
+    schema: bool_schema,
+};
+
+pub const CACHE_SECONDS: Param = Param {
+    name: "cacheSeconds",
+    description: "How long to cache the response for",
+    schema: || {
+        json!({
+            "type": "integer",
+            "minimum": DEFAULT_CACHE_SECONDS,
+            "default": DEFAULT_CACHE_SECONDS,
+        })
+    },
+};
+
+pub const BRANCH: Param = Param {
+    name: "branch",
+    description: "Git branch to count. Defaults to the repository's default branch.",
+    schema: || json!({ "type": "string" }),
+};
+
+pub const LANGUAGE: Param = Param {
+    name: "language",
+    description: "Comma-separated, case-sensitive list of languages to count",
+    schema: || json!({ "type": "string", "examples": ["Rust,JavaScript"] }),
+};
+
+pub const SORT: Param = Param {
+    name: "sort",
+    description: "Metric used to sort the csv, markdown, html, and files formats",
+    schema: || enum_schema(Category::iter(), Category::default()),
+};
+
+pub const TOP: Param = Param {
+    name: "top",
+    description: "Number of languages shown in tables before the rest are combined",
+    schema: || json!({ "type": "integer", "minimum": 1 }),
+};
+
+pub const LIMIT: Param = Param {
+    name: "limit",
+    description: "Maximum number of files returned by the files format",
+    schema: || {
+        json!({
+            "type": "integer",
+            "minimum": 1,
+            "maximum": MAX_FILE_LIMIT,
+            "default": DEFAULT_FILE_LIMIT,
+        })
+    },
+};
+
+pub const OFFSET: Param = Param {
+    name: "offset",
+    description: "Number of files to skip in the files format",
+    schema: || json!({ "type": "integer", "minimum": 0, "default": 0 }),
+};
+
+pub const FILTER: Param = Param {
+    name: "filter",
+    description: "Glob pattern that file paths must match in the files format",
+    schema: || json!({ "type": "string", "examples": ["src/**/*.rs"] }),
+};
+
+pub const SOURCE: Param = Param {
+    name: "source",
+    description: "Where file counts come from. `api` lists files through the forge's API instead \
+                  of cloning when `category=files` and the format is svg, png, or text. `auto` \
+                  uses the API only for hosts the server enables it for.",
+    schema: || enum_schema(Source::iter(), Source::default()),
+};
+
+pub const SIGNATURE: Param = Param {
+    name: "sig",
+    description: "HMAC-SHA256 signature of the repository path and query, required to use \
+                  configured credentials when signing is enabled",
+    schema: || json!({ "type": "string", "pattern": "^[0-9a-f]{64}$" }),
+};
+
+pub const EXPIRES: Param = Param {
+    name: "exp",
+    description: "Unix timestamp in seconds after which a signed URL expires",
+    schema: || json!({ "type": "integer", "minimum": 0 }),
+};
+
+/// Every query parameter of the badge route, in the order they're documented.
+pub const ALL: [&Param; 21] = [
+    &NAMESPACE,
+    &CATEGORY,
+    &FORMAT,
+    &STYLE,
+    &LABEL_COLOR,
+    &COLOR,
+    &LABEL,
+    &LOGO,
+    &LOGO_AS_LABEL,
+    &RAW,
+    &CACHE_SECONDS,
+    &BRANCH,
+    &LANGUAGE,
+    &SORT,
+    &TOP,
+    &LIMIT,
+    &OFFSET,
+    &FILTER,
+    &SOURCE,
+    &SIGNATURE,
+    &EXPIRES,
+];
+
+fn enum_schema<T: Into<&'static str>>(choices: impl Iterator<Item = T>, default: T) -> Value {
+    let choices: Vec<&str> = choices.map(Into::into).collect();
+    let default: &str = default.into();
+    json!({ "type": "string", "enum": choices, "default": default })
+}
+
+fn color_schema(default: Color) -> Value {
+    let examples: Vec<&str> = Color::named().map(Into::into).collect();
+    let default: &str = (&default).into();
+    json!({ "type": "string", "default": default, "examples": examples })
+}
+
+fn bool_schema() -> Value {
+    json!({ "type": "string", "enum": ["1", "true", "0", "false"], "default": "false" })
+}
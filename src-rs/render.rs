@@ -0,0 +1,424 @@
+use std::fmt::Write;
+use std::sync::{Arc, LazyLock};
+
+use resvg::usvg::fontdb::Database;
+use resvg::{tiny_skia, usvg};
+use rsbadges::Badge;
+use serde_json::json;
+use strum::IntoEnumIterator;
+use tokei::Language;
+use url::Url;
+
+use crate::category::Category;
+use crate::color::Color;
+use crate::content_type::ContentType;
+use crate::error::ApiError;
+use crate::settings::Settings;
+use crate::stats;
+use crate::style::Style;
+use crate::theme::{DEFAULT_COLOR, DEFAULT_LABEL_COLOR, Theme};
+
+const BILLION: usize = 1_000_000_000;
+const MILLION: usize = 1_000_000;
+const THOUSAND: usize = 1_000;
+const TABLE_COLUMNS: [Category; 5] = [
+    Category::Files,
+    Category::Lines,
+    Category::Code,
+    Category::Comments,
+    Category::Blanks,
+];
+const ERROR_LABEL: &str = "tokei";
+const PREVIEW_TEMPLATE: &str = include_str!("templates/preview.html");
+const BUILDER_TEMPLATE: &str = include_str!("templates/builder.html");
+// Serverless images ship without fonts, so badge text would be dropped from PNGs without one
+const EMBEDDED_FONT: &[u8] = include_bytes!("fonts/DejaVuSans.ttf");
+const EMBEDDED_FONT_FAMILY: &str = "DejaVu Sans";
+
+static FONT_DB: LazyLock<Arc<Database>> = LazyLock::new(|| {
+    let mut font_db = embedded_fonts();
+    font_db.load_system_fonts();
+    Arc::new(font_db)
+});
+
+pub fn format_amount(amount: usize, raw: bool) -> String {
+    if raw {
+        amount.to_string()
+    } else if amount >= BILLION {
+        format!("{:.1}B", trim_and_float(amount, BILLION))
+    } else if amount >= MILLION {
+        format!("{:.1}M", trim_and_float(amount, MILLION))
+    } else if amount >= THOUSAND {
+        format!("{:.1}K", trim_and_float(amount, THOUSAND))
+    } else {
+        amount.to_string()
+    }
+}
+
+fn trim_and_float(num: usize, trim: usize) -> f64 {
+    (num as f64) / (trim as f64)
+}
+
+pub fn badge(settings: &Settings, stats: &Language) -> Result<String, Box<dyn std::error::Error>> {
+    let amount = format_amount(settings.category.stats(stats), settings.raw);
+    let label = match &settings.label {
+        Some(label) => label.as_str(),
+        None => settings.category.description(),
+    };
+
+    let badge = Badge {
+        label_text: label.to_owned(),
+        label_color: settings.theme.label_color.to_string(),
+        msg_text: amount.clone(),
+        msg_color: settings.theme.color.to_string(),
+        logo: settings.logo.clone().unwrap_or_default(),
+        // data urls can't be embedded
+        embed_logo: settings
+            .logo
+            .as_ref()
+            .map(|l| !l.starts_with("data:"))
+            .unwrap_or(false),
+        msg_title: amount,
+        label_title: label.to_owned(),
+        use_logo_as_label: settings.logo_as_label,
+        ..Badge::default()
+    };
+    let badge_style = settings.theme.style.to_badge_style(badge);
+    Ok(badge_style.generate_svg()?)
+}
+
+/// Badge shown in place of the requested one when the request fails. The message is always red
+/// so failures stand out, but the rest of the theme is kept so it still fits in with its
+/// surroundings.
+pub fn error_badge(error: &ApiError, theme: &Theme) -> Result<String, Box<dyn std::error::Error>> {
+    let badge = Badge {
+        label_text: ERROR_LABEL.to_owned(),
+        label_color: theme.label_color.to_string(),
+        msg_text: error.summary().to_owned(),
+        msg_color: Color::Red.to_string(),
+        msg_title: error.to_string(),
+        label_title: ERROR_LABEL.to_owned(),
+        ..Badge::default()
+    };
+    Ok(theme.style.to_badge_style(badge).generate_svg()?)
+}
+
+pub fn text(settings: &Settings, stats: &Language) -> String {
+    format_amount(settings.category.stats(stats), settings.raw)
+}
+
+pub fn prometheus(repo: &str, stats: &Language) -> String {
+    let repo = escape_label(repo);
+    let languages = stats::by_language(stats);
+    let mut lines = String::new();
+    let mut files = String::new();
+    for (language_type, language) in &languages {
+        let language_name = escape_label(language_type.name());
+        for category in Category::iter() {
+            let amount = category.stats(language);
+            if category == Category::Files {
+                writeln!(
+                    files,
+                    "tokei_files{{repo=\"{repo}\",language=\"{language_name}\"}} {amount}"
+                )
+            } else {
+                let kind: &str = category.into();
+                writeln!(
+                    lines,
+                    "tokei_lines{{repo=\"{repo}\",language=\"{language_name}\",kind=\"{kind}\"}} \
+                     {amount}"
+                )
+            }
+            .expect("writing to a string should not fail");
+        }
+    }
+
+    format!(
+        "# HELP tokei_lines Number of lines per language and kind.\n# TYPE tokei_lines \
+         gauge\n{lines}# HELP tokei_files Number of files per language.\n# TYPE tokei_files \
+         gauge\n{files}"
+    )
+}
+
+pub fn csv(settings: &Settings, stats: &Language) -> String {
+    let mut csv = String::from("language");
+    for column in TABLE_COLUMNS {
+        let header: &str = column.into();
+        csv += ",";
+        csv += header;
+    }
+    csv += "\n";
+
+    for (name, language) in stats::ranked(stats, settings.sort, settings.top) {
+        csv += &escape_csv(&name);
+        for column in TABLE_COLUMNS {
+            write!(csv, ",{}", column.stats(&language))
+                .expect("writing to a string should not fail");
+        }
+        csv += "\n";
+    }
+    csv
+}
+
+pub fn markdown(settings: &Settings, stats: &Language) -> String {
+    let mut markdown = String::from(
+        "| Language | Files | Lines | Code | Comments | Blanks |\n| :--- | ---: | ---: | ---: | \
+         ---: | ---: |\n",
+    );
+
+    for (name, language) in stats::ranked(stats, settings.sort, settings.top) {
+        markdown += "| ";
+        markdown += &name.replace('|', "\\|");
+        for column in TABLE_COLUMNS {
+            write!(markdown, " | {}", column.stats(&language))
+                .expect("writing to a string should not fail");
+        }
+        markdown += " |\n";
+    }
+    markdown
+}
+
+pub fn files(settings: &Settings, stats: &Language) -> String {
+    let files = stats::files(stats, settings.sort, settings.filter.as_ref());
+    let page: Vec<_> = files
+        .iter()
+        .skip(settings.offset)
+        .take(settings.limit)
+        .map(|(language_type, report)| {
+            let stats = report.stats.summarise();
+            json!({
+                "path": report.name.to_string_lossy(),
+                "language": language_type.name(),
+                "lines": stats.lines(),
+                "code": stats.code,
+                "comments": stats.comments,
+                "blanks": stats.blanks,
+            })
+        })
+        .collect();
+
+    json!({
+        "total": files.len(),
+        "offset": settings.offset,
+        "limit": settings.limit,
+        "files": page,
+    })
+    .to_string()
+}
+
+pub fn png(svg: &str) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    render_png(svg, FONT_DB.clone())
+}
+
+/// Badges ask for Verdana, Geneva, DejaVu Sans, then sans-serif, so DejaVu Sans is bundled to
+/// cover hosts that have none of them installed.
+fn embedded_fonts() -> Database {
+    let mut font_db = Database::new();
+    font_db.load_font_data(EMBEDDED_FONT.to_vec());
+    font_db.set_sans_serif_family(EMBEDDED_FONT_FAMILY);
+    font_db
+}
+
+fn render_png(svg: &str, fontdb: Arc<Database>) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let options = usvg::Options {
+        fontdb,
+        ..Default::default()
+    };
+    let tree = usvg::Tree::from_str(svg, &options)?;
+    let size = tree.size().to_int_size();
+    let mut pixmap =
+        tiny_skia::Pixmap::new(size.width(), size.height()).ok_or("Badge has an invalid size")?;
+    resvg::render(&tree, tiny_skia::Transform::default(), &mut pixmap.as_mut());
+    Ok(pixmap.encode_png()?)
+}
+
+/// Preview page showing the badge in every style along with the per-language table and embed
+/// snippets for `badge_url`. `sign` is applied to every link to the badge, after its style is set.
+pub fn html(
+    repo: &str,
+    badge_url: &Url,
+    sign: &dyn Fn(Url) -> Url,
+    settings: &Settings,
+    stats: &Language,
+) -> String {
+    let label = match &settings.label {
+        Some(label) => label.as_str(),
+        None => settings.category.description(),
+    };
+    let repo = escape_html(repo);
+
+    let mut badges = String::new();
+    for style in Style::iter() {
+        let style_name: &str = style.into();
+        let style_url = escape_html(sign(with_query(badge_url, "style", style_name)).as_str());
+        write!(
+            badges,
+            "<figure><img src=\"{style_url}\" \
+             alt=\"{style_name}\"><figcaption>{style_name}</figcaption></figure>"
+        )
+        .expect("writing to a string should not fail");
+    }
+
+    let mut rows = String::new();
+    for (name, language) in stats::ranked(stats, settings.sort, settings.top) {
+        write!(rows, "<tr><td>{}</td>", escape_html(&name))
+            .expect("writing to a string should not fail");
+        for column in TABLE_COLUMNS {
+            write!(rows, "<td>{}</td>", column.stats(&language))
+                .expect("writing to a string should not fail");
+        }
+        rows += "</tr>";
+    }
+
+    let badge_url = sign(badge_url.clone());
+    let snippets = [
+        ("Markdown", format!("![{label}]({badge_url})")),
+        ("HTML", format!("<img src=\"{badge_url}\" alt=\"{label}\">")),
+        (
+            "reStructuredText",
+            format!(".. image:: {badge_url}\n   :alt: {label}"),
+        ),
+        ("AsciiDoc", format!("image:{badge_url}[{label}]")),
+    ];
+    let mut embeds = String::new();
+    for (name, snippet) in snippets {
+        write!(
+            embeds,
+            "<h3>{name}</h3><pre><code>{}</code></pre>",
+            escape_html(&snippet)
+        )
+        .expect("writing to a string should not fail");
+    }
+
+    PREVIEW_TEMPLATE
+        .replace("{{repo}}", &repo)
+        .replace("{{badges}}", &badges)
+        .replace("{{rows}}", &rows)
+        .replace("{{embeds}}", &embeds)
+}
+
+/// Badge builder page with form controls for every supported option. `readme` is rendered
+/// below the form if supplied.
+pub fn builder(readme: Option<&str>) -> String {
+    BUILDER_TEMPLATE
+        .replace(
+            "{{categories}}",
+            &options(Category::iter(), Category::default()),
+        )
+        .replace("{{styles}}", &options(Style::iter(), Style::default()))
+        .replace(
+            "{{formats}}",
+            &options(ContentType::iter(), ContentType::default()),
+        )
+        .replace(
+            "{{label_colors}}",
+            &options(Color::named(), DEFAULT_LABEL_COLOR),
+        )
+        .replace("{{colors}}", &options(Color::named(), DEFAULT_COLOR))
+        .replace(
+            "{{readme}}",
+            &readme
+                .map(|readme| format!("<article class=\"readme\">{readme}</article>"))
+                .unwrap_or_default(),
+        )
+}
+
+fn options<T>(choices: impl Iterator<Item = T>, default: T) -> String
+where
+    T: PartialEq + Into<&'static str>,
+{
+    let mut options = String::new();
+    for choice in choices {
+        let selected = if choice == default { " selected" } else { "" };
+        let name: &str = choice.into();
+        write!(
+            options,
+            "<option value=\"{name}\"{selected}>{name}</option>"
+        )
+        .expect("writing to a string should not fail");
+    }
+    options
+}
+
+fn with_query(url: &Url, key: &str, value: &str) -> Url {
+    let mut url = url.clone();
+    let pairs: Vec<_> = url
+        .query_pairs()
+        .filter(|(k, _)| k != key)
+        .map(|(k, v)| (k.into_owned(), v.into_owned()))
+        .collect();
+    url.query_pairs_mut()
+        .clear()
+        .extend_pairs(pairs)
+        .append_pair(key, value);
+    url
+}
+
+fn escape_html(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn escape_csv(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_owned()
+    }
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn png_renders_text_with_embedded_font() {
+        let svg = concat!(
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="60" height="20">"#,
+            r#"<text x="2" y="15" font-family="Verdana,Geneva,DejaVu Sans,sans-serif" "#,
+            r#"font-size="11">tokei</text></svg>"#,
+        );
+        let png = render_png(svg, Arc::new(embedded_fonts())).unwrap();
+        let pixmap = tiny_skia::Pixmap::decode_png(&png).unwrap();
+        assert!(pixmap.pixels().iter().any(|p| p.alpha() > 0));
+    }
+
+    #[test]
+    fn html_signs_every_badge_link() {
+        let settings = Settings::from_query(&Default::default()).unwrap();
+        let badge_url = Url::parse("https://example.com/tokei/github/owner/repo?exp=1").unwrap();
+        let sign = |mut url: Url| {
+            url.query_pairs_mut().append_pair("sig", "signed");
+            url
+        };
+        let page = html("repo", &badge_url, &sign, &settings, &Language::new());
+
+        let links: Vec<_> = page
+            .match_indices("https://example.com/tokei/")
+            .map(|(i, _)| &page[i..])
+            .collect();
+        assert_eq!(links.len(), Style::iter().count() + 4);
+        for link in links {
+            let end = ["\"", "&quot;", ")", "\n", "["]
+                .iter()
+                .filter_map(|end| link.find(end))
+                .min()
+                .unwrap();
+            assert!(
+                link[..end].ends_with("sig=signed"),
+                "{} isn't signed",
+                &link[..end]
+            );
+        }
+    }
+}
@@ -1,71 +1,224 @@
 use std::borrow::Cow;
-use std::collections::HashMap;
-use std::hash::{DefaultHasher, Hasher};
+use std::collections::{BTreeSet, HashMap};
+
+use globset::{Glob, GlobMatcher};
+use sha2::{Digest, Sha256};
+use tokei::LanguageType;
 
 use crate::category::Category;
 use crate::content_type::ContentType;
+use crate::error::ApiError;
+use crate::forge_api::Source;
+use crate::params;
 use crate::theme::Theme;
 
-const DEFAULT_CACHE_SECONDS: u32 = 60;
+pub const DEFAULT_CACHE_SECONDS: u32 = 60;
+pub const DEFAULT_FILE_LIMIT: usize = 100;
+pub const MAX_FILE_LIMIT: usize = 1000;
+// Bump when the way statistics are counted changes so old cache entries aren't reused
+const FINGERPRINT_VERSION: &str = "v1";
 
 pub struct Settings {
     pub category: Category,
+    pub sort: Category,
+    pub top: Option<usize>,
+    pub limit: usize,
+    pub offset: usize,
+    pub filter: Option<GlobMatcher>,
     pub content_type: ContentType,
     pub theme: Theme,
     pub cache_seconds: u32,
     pub label: Option<String>,
     pub logo: Option<String>,
     pub logo_as_label: bool,
+    pub raw: bool,
     pub branch: Option<String>,
     pub languages: Option<Vec<String>>,
+    pub source: Source,
 }
 
 impl Settings {
     pub fn from_query(query: &HashMap<String, Cow<str>>) -> Result<Self, &'static str> {
         let category = Category::from_query(query)?;
+        let sort = Category::sort_from_query(query)?;
+        let top = match params::TOP.get(query) {
+            Some(top) => match top.parse() {
+                Ok(top) if top > 0 => Some(top),
+                _ => return Err("Invalid top parameter. Must be a positive integer"),
+            },
+            None => None,
+        };
+        let limit = match params::LIMIT.get(query) {
+            Some(limit) => match limit.parse() {
+                Ok(limit) if limit > 0 && limit <= MAX_FILE_LIMIT => limit,
+                _ => return Err("Invalid limit parameter. Must be an integer from 1 to 1000"),
+            },
+            None => DEFAULT_FILE_LIMIT,
+        };
+        let offset = match params::OFFSET.get(query) {
+            Some(offset) => offset
+                .parse()
+                .map_err(|_| "Invalid offset parameter. Must be a non-negative integer")?,
+            None => 0,
+        };
+        let filter = match params::FILTER.get(query) {
+            Some(filter) => Some(
+                Glob::new(filter)
+                    .map_err(|_| "Invalid filter parameter. Must be a valid glob pattern")?
+                    .compile_matcher(),
+            ),
+            None => None,
+        };
         let content_type = ContentType::from_query(query)?;
         let theme = Theme::from_query(query)?;
+        let source = Source::from_query(query)?;
 
-        let label = query.get("label").map(|label| label.to_string());
-        let logo = query.get("logo").map(|label| label.to_string());
-        let logo_as_label = query
-            .get("logoAsLabel")
+        let label = params::LABEL.get(query).map(|label| label.to_string());
+        let logo = params::LOGO.get(query).map(|label| label.to_string());
+        let logo_as_label = params::LOGO_AS_LABEL
+            .get(query)
             .map(|l| l == "1" || l.to_lowercase() == "true")
             .unwrap_or(false);
+        let raw = params::RAW
+            .get(query)
+            .map(|r| r == "1" || r.to_lowercase() == "true")
+            .unwrap_or(false);
 
-        let mut cache_seconds: u32 = match query.get("cacheSeconds") {
+        let mut cache_seconds: u32 = match params::CACHE_SECONDS.get(query) {
             Some(seconds) => seconds.parse().unwrap_or(DEFAULT_CACHE_SECONDS),
             None => DEFAULT_CACHE_SECONDS,
         };
         if cache_seconds < DEFAULT_CACHE_SECONDS {
             cache_seconds = DEFAULT_CACHE_SECONDS;
         }
-        let branch = query.get("branch").map(|branch| branch.to_string());
-        let languages = query
-            .get("language")
+        let branch = params::BRANCH.get(query).map(|branch| branch.to_string());
+        let languages = params::LANGUAGE
+            .get(query)
             .map(|l| l.split(",").map(ToOwned::to_owned).collect());
 
         Ok(Self {
             cache_seconds,
             category,
+            sort,
+            top,
+            limit,
+            offset,
+            filter,
             theme,
             content_type,
             label,
             logo,
             logo_as_label,
+            raw,
             branch,
             languages,
+            source,
         })
     }
 
-    pub fn loc_cache_key(&self) -> u64 {
-        let mut hasher = DefaultHasher::new();
-        if let Some(languages) = &self.languages {
-            for l in languages {
-                hasher.write(l.as_bytes());
+    pub fn language_filter(&self) -> Result<Option<Vec<LanguageType>>, ApiError> {
+        let Some(languages) = &self.languages else {
+            return Ok(None);
+        };
+        languages
+            .iter()
+            .map(|l| LanguageType::from_name(l).ok_or_else(|| ApiError::UnknownLanguage(l.clone())))
+            .collect::<Result<_, _>>()
+            .map(Some)
+    }
+
+    /// Identifies the settings that change what tokei counts. Rendering options like the category
+    /// aren't included since every category is derived from the same statistics.
+    ///
+    /// The fingerprint is a SHA-256 hex digest, so it's stable across processes and can be used
+    /// with persistent caches. Languages are sorted and deduplicated so their order doesn't
+    /// matter, and every value is length-prefixed so different lists can't hash the same input.
+    pub fn counting_fingerprint(&self) -> String {
+        let mut hasher = Sha256::new();
+        write_field(&mut hasher, FINGERPRINT_VERSION);
+        match &self.languages {
+            Some(languages) => {
+                let languages: BTreeSet<&str> = languages.iter().map(String::as_str).collect();
+                write_field(&mut hasher, "languages");
+                hasher.update((languages.len() as u64).to_be_bytes());
+                for language in languages {
+                    write_field(&mut hasher, language);
+                }
             }
+            None => write_field(&mut hasher, "all"),
         }
-        hasher.write(self.category.description().as_bytes());
-        hasher.finish()
+        format!("{:x}", hasher.finalize())
+    }
+}
+
+fn write_field(hasher: &mut Sha256, value: &str) {
+    hasher.update((value.len() as u64).to_be_bytes());
+    hasher.update(value.as_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fingerprint(languages: Option<&[&str]>) -> String {
+        let mut settings = Settings::from_query(&HashMap::new()).unwrap();
+        settings.languages = languages.map(|l| l.iter().map(|l| l.to_string()).collect());
+        settings.counting_fingerprint()
+    }
+
+    #[test]
+    fn reads_camel_case_parameters_from_lowercased_keys() {
+        let query: HashMap<String, Cow<str>> = [
+            ("labelcolor", "red"),
+            ("logoaslabel", "true"),
+            ("cacheseconds", "300"),
+        ]
+        .into_iter()
+        .map(|(k, v)| (k.to_owned(), Cow::Borrowed(v)))
+        .collect();
+        let settings = Settings::from_query(&query).unwrap();
+        assert_eq!(settings.theme.label_color, crate::color::Color::Red);
+        assert!(settings.logo_as_label);
+        assert_eq!(settings.cache_seconds, 300);
+    }
+
+    #[test]
+    fn fingerprint_separates_languages() {
+        assert_ne!(
+            fingerprint(Some(&["Ru", "st"])),
+            fingerprint(Some(&["Rust"]))
+        );
+    }
+
+    #[test]
+    fn fingerprint_ignores_order() {
+        assert_eq!(
+            fingerprint(Some(&["Rust", "Python"])),
+            fingerprint(Some(&["Python", "Rust"]))
+        );
+    }
+
+    #[test]
+    fn fingerprint_ignores_duplicates() {
+        assert_eq!(
+            fingerprint(Some(&["Rust", "Rust", "Python"])),
+            fingerprint(Some(&["Python", "Rust"]))
+        );
+    }
+
+    #[test]
+    fn fingerprint_distinguishes_no_filter_from_empty_filter() {
+        assert_ne!(fingerprint(None), fingerprint(Some(&[])));
+        assert_ne!(fingerprint(Some(&[])), fingerprint(Some(&[""])));
+    }
+
+    #[test]
+    fn fingerprint_is_stable() {
+        // Changing this breaks existing entries in persistent caches, so bump
+        // FINGERPRINT_VERSION instead of updating it
+        assert_eq!(
+            fingerprint(Some(&["Rust"])),
+            "505d96509d286d897ae2d68749370e2ac7f966be7b2f46240adeb4c01dafbb3d"
+        );
     }
 }
@@ -0,0 +1,299 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::env;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use url::Url;
+use url::form_urlencoded::Serializer;
+
+use crate::error::ApiError;
+use crate::params;
+
+const SECRET_VAR: &str = "TOKEI_SIGNING_SECRET";
+const SIGNATURE_KEY: &str = params::SIGNATURE.name;
+const EXPIRES_KEY: &str = params::EXPIRES.name;
+// Path segments are passed to the handler as query parameters, but they're signed separately
+const PATH_KEYS: [&str; 3] = ["domain", "user", "repo"];
+
+/// Signs and verifies badge URLs so private repositories can be embedded without exposing a
+/// token. The signature covers the repository path and every query parameter, including the
+/// optional `exp` expiry timestamp.
+pub struct Signer {
+    secret: Vec<u8>,
+}
+
+impl Signer {
+    pub fn new(secret: impl Into<Vec<u8>>) -> Self {
+        Self {
+            secret: secret.into(),
+        }
+    }
+
+    /// Reads the secret from `TOKEI_SIGNING_SECRET`. Returns `None` if signing isn't configured.
+    pub fn from_env() -> Option<Self> {
+        env::var(SECRET_VAR)
+            .ok()
+            .filter(|s| !s.is_empty())
+            .map(Self::new)
+    }
+
+    /// Builds a signed badge URL. `expires_at` is a unix timestamp in seconds after which the
+    /// URL stops working.
+    pub fn signed_url(
+        &self,
+        base_url: &Url,
+        (domain, user, repo): (&str, &str, &str),
+        params: &[(&str, &str)],
+        expires_at: Option<u64>,
+    ) -> Url {
+        let mut params: Vec<(String, String)> = params
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        if let Some(expires_at) = expires_at {
+            params.push((EXPIRES_KEY.to_owned(), expires_at.to_string()));
+        }
+        let signature = self.sign(domain, user, repo, &params);
+
+        let mut url = base_url.clone();
+        url.path_segments_mut()
+            .expect("base URL should be able to have a path")
+            .pop_if_empty()
+            .extend(["tokei", domain, user, repo]);
+        url.query_pairs_mut()
+            .extend_pairs(params)
+            .append_pair(SIGNATURE_KEY, &signature);
+        url
+    }
+
+    /// Checks the `sig` and `exp` parameters of a request.
+    pub fn verify(&self, query: &HashMap<String, Cow<str>>) -> Result<(), ApiError> {
+        let signature = query
+            .get(SIGNATURE_KEY)
+            .and_then(|s| decode_hex(s))
+            .ok_or_else(|| ApiError::Forbidden("Invalid signature".to_owned()))?;
+        let [domain, user, repo] =
+            PATH_KEYS.map(|k| query.get(k).map(AsRef::as_ref).unwrap_or_default());
+        let params: Vec<_> = query
+            .iter()
+            .filter(|(k, _)| *k != SIGNATURE_KEY && !PATH_KEYS.contains(&k.as_str()))
+            .map(|(k, v)| (k.clone(), v.to_string()))
+            .collect();
+
+        self.mac(domain, user, repo, &params)
+            .verify_slice(&signature)
+            .map_err(|_| ApiError::Forbidden("Invalid signature".to_owned()))?;
+
+        if let Some(expires_at) = query.get(EXPIRES_KEY) {
+            let expires_at: u64 = expires_at
+                .parse()
+                .map_err(|_| ApiError::InvalidParameter("Invalid exp parameter"))?;
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs();
+            if now > expires_at {
+                return Err(ApiError::Forbidden("Signed URL has expired".to_owned()));
+            }
+        }
+        Ok(())
+    }
+
+    /// Signature of a repository path and its query parameters, excluding `sig` itself.
+    pub fn sign(
+        &self,
+        domain: &str,
+        user: &str,
+        repo: &str,
+        params: &[(String, String)],
+    ) -> String {
+        format!(
+            "{:x}",
+            self.mac(domain, user, repo, params).finalize().into_bytes()
+        )
+    }
+
+    fn mac(
+        &self,
+        domain: &str,
+        user: &str,
+        repo: &str,
+        params: &[(String, String)],
+    ) -> Hmac<Sha256> {
+        // Keys are case-insensitive in requests, so normalize them before sorting to make the
+        // signature independent of parameter order and casing
+        let mut params: Vec<_> = params
+            .iter()
+            .map(|(k, v)| (k.to_ascii_lowercase(), v.as_str()))
+            .collect();
+        params.sort();
+        let query = Serializer::new(String::new()).extend_pairs(params).finish();
+
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.secret).expect("HMAC should accept any key size");
+        mac.update(format!("{domain}/{user}/{repo}\n{query}").as_bytes());
+        mac
+    }
+}
+
+fn decode_hex(value: &str) -> Option<Vec<u8>> {
+    if !value.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..value.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(value.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PATH: (&str, &str, &str) = ("github", "owner", "repo");
+
+    fn signer() -> Signer {
+        Signer::new("secret")
+    }
+
+    // Mirrors how the handler sees a request: path segments as parameters and lowercased keys
+    fn query(url: &Url) -> HashMap<String, Cow<'static, str>> {
+        let segments: Vec<_> = url.path_segments().unwrap().skip(1).collect();
+        PATH_KEYS
+            .iter()
+            .zip(segments)
+            .map(|(k, v)| (k.to_string(), Cow::Owned(v.to_owned())))
+            .chain(
+                url.query_pairs()
+                    .map(|(k, v)| (k.to_ascii_lowercase(), Cow::Owned(v.into_owned()))),
+            )
+            .collect()
+    }
+
+    fn signed(
+        params: &[(&str, &str)],
+        expires_at: Option<u64>,
+    ) -> HashMap<String, Cow<'static, str>> {
+        let base = Url::parse("https://example.com").unwrap();
+        query(&signer().signed_url(&base, PATH, params, expires_at))
+    }
+
+    fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    }
+
+    fn is_forbidden(result: Result<(), ApiError>) -> bool {
+        matches!(result, Err(ApiError::Forbidden(_)))
+    }
+
+    #[test]
+    fn verifies_signed_url() {
+        let query = signed(&[("category", "code"), ("labelColor", "red")], None);
+        signer().verify(&query).unwrap();
+        let query = signed(&[], Some(now() + 60));
+        signer().verify(&query).unwrap();
+    }
+
+    #[test]
+    fn rejects_other_secret() {
+        let query = signed(&[("category", "code")], None);
+        assert!(is_forbidden(Signer::new("other").verify(&query)));
+    }
+
+    #[test]
+    fn rejects_tampered_param() {
+        let mut query = signed(&[("category", "code")], None);
+        query.insert("category".to_owned(), Cow::Borrowed("lines"));
+        assert!(is_forbidden(signer().verify(&query)));
+
+        let mut query = signed(&[("category", "code")], None);
+        query.insert("style".to_owned(), Cow::Borrowed("flat"));
+        assert!(is_forbidden(signer().verify(&query)));
+    }
+
+    #[test]
+    fn rejects_tampered_path() {
+        for key in PATH_KEYS {
+            let mut query = signed(&[("category", "code")], None);
+            query.insert(key.to_owned(), Cow::Borrowed("other"));
+            assert!(is_forbidden(signer().verify(&query)), "{key} wasn't signed");
+        }
+    }
+
+    #[test]
+    fn rejects_tampered_expiry() {
+        let mut query = signed(&[], Some(now() + 60));
+        query.insert(
+            EXPIRES_KEY.to_owned(),
+            Cow::Owned((now() + 3600).to_string()),
+        );
+        assert!(is_forbidden(signer().verify(&query)));
+
+        let mut query = signed(&[], Some(now() + 60));
+        query.remove(EXPIRES_KEY);
+        assert!(is_forbidden(signer().verify(&query)));
+    }
+
+    #[test]
+    fn rejects_expired_url() {
+        let query = signed(&[], Some(now() - 1));
+        match signer().verify(&query) {
+            Err(ApiError::Forbidden(message)) => assert_eq!(message, "Signed URL has expired"),
+            result => panic!("expected an expired error, got {result:?}"),
+        }
+    }
+
+    #[test]
+    fn signature_ignores_param_order_and_key_case() {
+        let (domain, user, repo) = PATH;
+        let params = |pairs: &[(&str, &str)]| -> Vec<(String, String)> {
+            pairs
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect()
+        };
+        assert_eq!(
+            signer().sign(
+                domain,
+                user,
+                repo,
+                &params(&[("labelColor", "red"), ("category", "code")])
+            ),
+            signer().sign(
+                domain,
+                user,
+                repo,
+                &params(&[("category", "code"), ("labelcolor", "red")])
+            ),
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_signature() {
+        let valid = signed(&[], None)[SIGNATURE_KEY].to_string();
+        for signature in [
+            "",
+            "abc",
+            "zz",
+            &valid[1..],
+            &format!("{valid}0"),
+            &valid.replace(|c: char| c.is_ascii_digit(), "g"),
+        ] {
+            let mut query = signed(&[], None);
+            query.insert(SIGNATURE_KEY.to_owned(), Cow::Owned(signature.to_owned()));
+            assert!(
+                is_forbidden(signer().verify(&query)),
+                "{signature} was accepted"
+            );
+        }
+
+        let mut query = signed(&[], None);
+        query.remove(SIGNATURE_KEY);
+        assert!(is_forbidden(signer().verify(&query)));
+    }
+}
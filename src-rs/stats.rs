@@ -0,0 +1,74 @@
+use globset::GlobMatcher;
+use tokei::{Language, LanguageType, Report};
+
+use crate::category::Category;
+
+const OTHER_LABEL: &str = "Other";
+
+/// Splits the combined totals returned by `Languages::total` back into one `Language` per
+/// language type.
+pub fn by_language(total: &Language) -> Vec<(LanguageType, Language)> {
+    total
+        .children
+        .iter()
+        .map(|(language_type, reports)| {
+            let mut language = Language {
+                reports: reports.clone(),
+                ..Language::new()
+            };
+            language.total();
+            (*language_type, language)
+        })
+        .collect()
+}
+
+/// Per-language statistics sorted in descending order by `sort`. If `top` is set, only that many
+/// languages are kept and the remainder is combined into a single "Other" row.
+pub fn ranked(total: &Language, sort: Category, top: Option<usize>) -> Vec<(String, Language)> {
+    let mut languages = by_language(total);
+    languages.sort_by(|(a_type, a), (b_type, b)| {
+        sort.stats(b)
+            .cmp(&sort.stats(a))
+            .then_with(|| a_type.name().cmp(b_type.name()))
+    });
+
+    let rest = match top {
+        Some(top) if top < languages.len() => languages.split_off(top),
+        _ => Vec::new(),
+    };
+    let mut rows: Vec<_> = languages
+        .into_iter()
+        .map(|(language_type, language)| (language_type.name().to_owned(), language))
+        .collect();
+
+    if !rest.is_empty() {
+        let mut other = Language::new();
+        for (_, language) in rest {
+            other.reports.extend(language.reports);
+        }
+        other.total();
+        rows.push((OTHER_LABEL.to_owned(), other));
+    }
+    rows
+}
+
+/// Every file report paired with its language, sorted in descending order by `sort`. Files that
+/// don't match `filter` are skipped.
+pub fn files<'a>(
+    total: &'a Language,
+    sort: Category,
+    filter: Option<&GlobMatcher>,
+) -> Vec<(LanguageType, &'a Report)> {
+    let mut files: Vec<_> = total
+        .children
+        .iter()
+        .flat_map(|(language_type, reports)| reports.iter().map(|r| (*language_type, r)))
+        .filter(|(_, report)| filter.is_none_or(|f| f.is_match(&report.name)))
+        .collect();
+    files.sort_by(|(_, a), (_, b)| {
+        sort.report_stats(b)
+            .cmp(&sort.report_stats(a))
+            .then_with(|| a.name.cmp(&b.name))
+    });
+    files
+}
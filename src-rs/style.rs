@@ -3,11 +3,14 @@ use std::collections::HashMap;
 use std::str::FromStr;
 
 use rsbadges::Badge;
-use strum_macros::EnumString;
+use strum_macros::{EnumIter, EnumString, IntoStaticStr};
 
-#[derive(PartialEq, Eq, Debug, EnumString)]
+use crate::params;
+
+#[derive(PartialEq, Eq, Debug, Default, Clone, Copy, EnumString, EnumIter, IntoStaticStr)]
 #[strum(serialize_all = "kebab-case", ascii_case_insensitive)]
 pub enum Style {
+    #[default]
     Flat,
     FlatSquare,
     Plastic,
@@ -17,12 +20,12 @@ pub enum Style {
 
 impl Style {
     pub fn from_query(query: &HashMap<String, Cow<str>>) -> Result<Self, &'static str> {
-        match query.get("style") {
+        match params::STYLE.get(query) {
             Some(style) => Self::from_str(style).map_err(|_| {
                 "Invalid style parameter. Choices are 'flat', 'flat-square', 'plastic', \
                  'for-the-badge', and 'social'"
             }),
-            None => Ok(Self::Flat),
+            None => Ok(Self::default()),
         }
     }
 
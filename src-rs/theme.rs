@@ -2,19 +2,33 @@ use std::borrow::Cow;
 use std::collections::HashMap;
 
 use crate::color::Color;
+use crate::params;
 use crate::style::Style;
 
+pub const DEFAULT_LABEL_COLOR: Color = Color::Grey;
+pub const DEFAULT_COLOR: Color = Color::Blue;
+
 pub struct Theme {
     pub style: Style,
     pub label_color: Color,
     pub color: Color,
 }
 
+impl Default for Theme {
+    fn default() -> Self {
+        Self {
+            style: Style::default(),
+            label_color: DEFAULT_LABEL_COLOR,
+            color: DEFAULT_COLOR,
+        }
+    }
+}
+
 impl Theme {
     pub fn from_query(query: &HashMap<String, Cow<str>>) -> Result<Self, &'static str> {
         let style = Style::from_query(query)?;
-        let label_color = Color::from_query(query, "labelColor", Color::Grey);
-        let color = Color::from_query(query, "color", Color::Blue);
+        let label_color = Color::from_query(query, &params::LABEL_COLOR, DEFAULT_LABEL_COLOR);
+        let color = Color::from_query(query, &params::COLOR, DEFAULT_COLOR);
         Ok(Self {
             style,
             label_color,